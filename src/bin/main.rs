@@ -1,10 +1,16 @@
-use butterfly_extractor::{Client, WebpageParser};
+use butterfly_extractor::{
+    ApiKeySource, Client, CloudVisionConfig, ColorNames, CsvConfig, HttpClient, HttpConfig,
+    ImageMode, LocalColorExtractor, NameOverrides, PageSource, ParseOptions, VisionFeature,
+    INDEX_URL,
+};
 use env_logger::Builder;
 use log::LevelFilter;
 extern crate clap;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use log::info;
+use std::fs::File;
+use std::time::Duration;
 
 fn main() {
     Builder::from_default_env()
@@ -13,34 +19,6 @@ fn main() {
         .default_format_timestamp(false)
         .init();
 
-    let mut client = Client::new(vec![
-        WebpageParser::new(
-            "old_north",
-            "旧北区",
-            "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall",
-        ),
-        WebpageParser::new(
-            "new_north",
-            "新北区",
-            "http://biokite.com/worldbutterfly/butterfly-NArc.htm#NAsa",
-        ),
-        WebpageParser::new(
-            "new_tropical",
-            "新熱帯区",
-            "http://biokite.com/worldbutterfly/butterfly-NTro.htm#NTmap",
-        ),
-        WebpageParser::new(
-            "india_australia",
-            "インド・オーストラリア区",
-            "http://biokite.com/worldbutterfly/butterfly-IOrs.htm#IOmap",
-        ),
-        WebpageParser::new(
-            "tropical_africa",
-            "熱帯アフリカ区",
-            "http://biokite.com/worldbutterfly/butterfly-TAfr.htm#TAmaps",
-        ),
-    ]);
-
     // CLI here
     let matches = App::new("My Super Program")
         .version("1.0")
@@ -64,9 +42,72 @@ fn main() {
             Arg::with_name("dominant_colors")
                 .short("d")
                 .long("dominant")
-                .help("Use google cloud vision api to obtain dominant color data")
+                .help("Obtain dominant color data, with google cloud vision api by default")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("color_backend")
+                .long("color-backend")
+                .help("Backend used to obtain dominant colors, local requires --image")
+                .takes_value(true)
+                .possible_values(&["cloud_vision", "local"])
+                .default_value("cloud_vision"),
+        )
+        .arg(
+            Arg::with_name("color_names")
+                .long("color-names")
+                .help("TOML file listing the color families used to name dominant colors")
+                .takes_value(true)
+                .value_name("PATH"),
+        )
+        .arg(
+            Arg::with_name("vision_endpoint")
+                .long("vision-endpoint")
+                .help("Url of google cloud vision images:annotate endpoint")
+                .takes_value(true)
+                .value_name("URL"),
+        )
+        .arg(
+            Arg::with_name("vision_key_file")
+                .long("vision-key-file")
+                .help("File which stores google cloud vision api key")
+                .takes_value(true)
+                .value_name("PATH")
+                .conflicts_with("vision_key_env"),
+        )
+        .arg(
+            Arg::with_name("vision_key_env")
+                .long("vision-key-env")
+                .help("Environment variable which stores google cloud vision api key")
+                .takes_value(true)
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::with_name("vision_max_results")
+                .long("vision-max-results")
+                .help("Number of colors requested for each image")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vision_image_uri")
+                .long("vision-image-uri")
+                .help("Send image urls to google cloud vision instead of uploading the images")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("vision_features")
+                .long("vision-features")
+                .help("Additional google cloud vision features, separated by comma")
+                .takes_value(true)
+                .use_delimiter(true)
+                .possible_values(&["labels", "crop_hints", "objects", "safe_search"]),
+        )
+        .arg(
+            Arg::with_name("vision_timeout")
+                .long("vision-timeout")
+                .help("Timeout of google cloud vision requests in seconds")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("json")
                 .short("j")
@@ -74,10 +115,410 @@ fn main() {
                 .help("Store data into json file")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("regions")
+                .long("regions")
+                .help("TOML file listing the region pages to extract data from")
+                .takes_value(true)
+                .default_value("./regions.toml"),
+        )
+        .arg(
+            Arg::with_name("discover")
+                .long("discover")
+                .help("Also extract regions linked from the index page which are not listed")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .help("Url or saved file of the index page used with --discover")
+                .takes_value(true)
+                .default_value(INDEX_URL),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .long("concurrency")
+                .help("Number of region pages fetched at the same time")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .help("Timeout of each http request in seconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .help("Number of times a failed http request is retried")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("user_agent")
+                .long("user-agent")
+                .help("User-Agent header sent with every http request")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate")
+                .long("rate")
+                .help("Average number of requests per second sent to each host")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("burst")
+                .long("burst")
+                .help("Number of requests sent to a host at once, also used as the number of parallel downloads")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ignore_robots")
+                .long("ignore-robots")
+                .help("Fetch pages even if robots.txt disallows them")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("name_overrides")
+                .long("name-overrides")
+                .help("JSON file of corrections for irregular name cells, used on top of the bundled ones")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("override_report")
+                .long("override-report")
+                .help("Store which name overrides were used into json file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lenient")
+                .long("lenient")
+                .help("Skip cells which could not be parsed, up to given number per region")
+                .takes_value(true)
+                .value_name("MAX_ERRORS"),
+        )
+        .arg(
+            Arg::with_name("parse_report")
+                .long("parse-report")
+                .help("Store cells skipped with --lenient into json file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("csv")
+                .long("csv")
+                .help("CSV file used to add distribution, diet and remarks")
+                .takes_value(true)
+                .default_value("./butterfly.csv"),
+        )
+        .arg(
+            Arg::with_name("csv_column")
+                .long("csv-column")
+                .help("Read a field from the column with given header, as FIELD=HEADER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("csv_report")
+                .long("csv-report")
+                .help("Store butterflies and rows which do not match between the website and CSV file, as csv if the file name ends with .csv or json otherwise")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no_csv")
+                .long("no-csv")
+                .help("Do not read CSV file")
+                .conflicts_with_all(&["csv", "csv_column", "csv_report"]),
+        )
+        .arg(
+            Arg::with_name("pages")
+                .long("pages")
+                .help("Read region pages saved as <dir_name>.htm in given directory")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("find-color")
+                .about("Find butterflies whose colors are the closest to given color")
+                .arg(
+                    Arg::with_name("color")
+                        .help("Color in 6 digit hex, such as #1e50c8")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("count")
+                        .short("k")
+                        .long("count")
+                        .help("Number of butterflies to show")
+                        .takes_value(true)
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .help("JSON file stored by --json")
+                        .takes_value(true)
+                        .default_value("./butterfly.json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Search butterflies by names, distribution and remarks")
+                .arg(
+                    Arg::with_name("query")
+                        .help("Terms separated by spaces, in kana, kanji or latin letters")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("count")
+                        .short("k")
+                        .long("count")
+                        .help("Number of butterflies to show")
+                        .takes_value(true)
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .help("JSON file stored by --json")
+                        .takes_value(true)
+                        .default_value("./butterfly.json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("taxonomy")
+                .about("Show family, genus and species of collected butterflies")
+                .arg(
+                    Arg::with_name("region")
+                        .long("region")
+                        .help("Only show given region, by name or directory name")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("Store the tree into json file instead of printing it")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .help("JSON file stored by --json")
+                        .takes_value(true)
+                        .default_value("./butterfly.json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Show butterflies added, removed or changed since an older json file")
+                .arg(
+                    Arg::with_name("old")
+                        .help("Older JSON file stored by --json")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .help("JSON file stored by --json")
+                        .takes_value(true)
+                        .default_value("./butterfly.json"),
+                ),
+        )
         .get_matches();
 
+    if let Some(find_matches) = matches.subcommand_matches("find-color") {
+        let butterfly_data = Client::from_path(find_matches.value_of("input").unwrap()).unwrap();
+        let count = find_matches
+            .value_of("count")
+            .unwrap()
+            .parse()
+            .expect("--count takes a number");
+        let color = find_matches.value_of("color").unwrap();
+
+        for (butterfly, distance) in butterfly_data.find_by_color(color, count).unwrap() {
+            println!(
+                "{:.2}\t{}\t{}\t{}",
+                distance, butterfly.jp_name, butterfly.eng_name, butterfly.region
+            );
+        }
+        return;
+    }
+
+    if let Some(search_matches) = matches.subcommand_matches("search") {
+        let butterfly_data = Client::from_path(search_matches.value_of("input").unwrap()).unwrap();
+        let count = search_matches
+            .value_of("count")
+            .unwrap()
+            .parse()
+            .expect("--count takes a number");
+        let query = search_matches.value_of("query").unwrap();
+
+        for hit in butterfly_data.search_index().search(query, count) {
+            println!(
+                "{}\t{}\t{}\t{}",
+                hit.score, hit.butterfly.jp_name, hit.butterfly.eng_name, hit.butterfly.region
+            );
+        }
+        return;
+    }
+
+    if let Some(taxonomy_matches) = matches.subcommand_matches("taxonomy") {
+        let butterfly_data =
+            Client::from_path(taxonomy_matches.value_of("input").unwrap()).unwrap();
+        let taxonomy = match taxonomy_matches.value_of("region") {
+            Some(region) => butterfly_data.region_taxonomy(region),
+            None => butterfly_data.taxonomy(),
+        };
+
+        if let Some(output) = taxonomy_matches.value_of("output") {
+            taxonomy.store_json(output).unwrap();
+            return;
+        }
+
+        for family in taxonomy.families.iter() {
+            println!(
+                "{} {}",
+                family.jp_name,
+                family.latin_name.as_deref().unwrap_or("")
+            );
+            for genus in family.genera.iter() {
+                println!("  {}", genus.name);
+                for species in genus.species.iter() {
+                    println!(
+                        "    {}\t{}\t{}",
+                        species.scientific_name,
+                        species.jp_names.join(","),
+                        species.regions.join(",")
+                    );
+                }
+            }
+            for jp_name in family.unclassified.iter() {
+                println!("  ?\t{}", jp_name);
+            }
+        }
+        return;
+    }
+
+    if let Some(diff_matches) = matches.subcommand_matches("diff") {
+        let old_data = Client::from_path(diff_matches.value_of("old").unwrap()).unwrap();
+        let butterfly_data = Client::from_path(diff_matches.value_of("input").unwrap()).unwrap();
+        let diff = butterfly_data.diff(&old_data);
+
+        for (mark, ids) in [
+            ("+", &diff.added),
+            ("-", &diff.removed),
+            ("~", &diff.changed),
+        ]
+        .iter()
+        {
+            for id in ids.iter() {
+                let butterfly = butterfly_data
+                    .butterflies
+                    .iter()
+                    .chain(old_data.butterflies.iter())
+                    .find(|b| &b.id == id)
+                    .unwrap();
+                println!(
+                    "{} {}\t{}\t{}\t{}",
+                    mark, id, butterfly.jp_name, butterfly.eng_name, butterfly.region
+                );
+            }
+        }
+        return;
+    }
+
+    let mut http_config = HttpConfig::default();
+    if let Some(timeout) = matches.value_of("timeout") {
+        http_config.timeout =
+            Duration::from_secs(timeout.parse().expect("--timeout takes a number"));
+    }
+    if let Some(retries) = matches.value_of("retries") {
+        http_config.retries = retries.parse().expect("--retries takes a number");
+    }
+    if let Some(user_agent) = matches.value_of("user_agent") {
+        http_config.user_agent = user_agent.to_string();
+    }
+    if let Some(rate) = matches.value_of("rate") {
+        http_config.requests_per_second = rate.parse().expect("--rate takes a number");
+    }
+    if let Some(burst) = matches.value_of("burst") {
+        http_config.burst = burst.parse().expect("--burst takes a number");
+    }
+    if matches.is_present("ignore_robots") {
+        http_config.respect_robots_txt = false;
+    }
+
+    let mut client = Client::from_config(matches.value_of("regions").unwrap())
+        .unwrap()
+        .with_http_client(HttpClient::new(http_config).unwrap());
+
+    if matches.is_present("discover") {
+        let index = matches.value_of("index").unwrap();
+        let index_source = if index.starts_with("http") {
+            PageSource::Http(index.to_string())
+        } else {
+            PageSource::file(index)
+        };
+        client = client.with_discovered_regions(&index_source).unwrap();
+    }
+
+    if let Some(page_dir) = matches.value_of("pages") {
+        client = client.with_page_dir(page_dir);
+    }
+
+    if let Some(overrides_path) = matches.value_of("name_overrides") {
+        client = client.with_name_overrides(NameOverrides::from_path(overrides_path).unwrap());
+    }
+
+    if let Some(max_errors) = matches.value_of("lenient") {
+        let error_threshold = max_errors.parse().expect("--lenient takes a number");
+        client = client.with_parse_options(ParseOptions::lenient(error_threshold));
+    }
+
+    if let Some(concurrency) = matches.value_of("concurrency") {
+        client =
+            client.with_concurrency(concurrency.parse().expect("--concurrency takes a number"));
+    }
+
     let mut butterfly_data = client.collect_datas().unwrap();
-    butterfly_data.fetch_csv_info();
+
+    if let Some(report_path) = matches.value_of("parse_report") {
+        let report_file = File::create(report_path).unwrap();
+        serde_json::to_writer_pretty(report_file, client.parse_report()).unwrap();
+    }
+
+    if let Some(report_path) = matches.value_of("override_report") {
+        let report_file = File::create(report_path).unwrap();
+        serde_json::to_writer_pretty(report_file, &client.name_override_report()).unwrap();
+    }
+    if !matches.is_present("no_csv") {
+        // The default csv file is skipped if missing, given ones must be read
+        let mut csv_config = CsvConfig {
+            path: matches.value_of("csv").unwrap().into(),
+            ..CsvConfig::default()
+        };
+        if let Some(columns) = matches.values_of("csv_column") {
+            for column in columns {
+                let mut field_header = column.splitn(2, '=');
+                let field = field_header.next().unwrap();
+                let header = field_header
+                    .next()
+                    .expect("--csv-column takes FIELD=HEADER");
+                csv_config.columns.set(field, header).unwrap();
+            }
+        }
+        if matches.occurrences_of("csv") > 0 || matches.is_present("csv_column") {
+            butterfly_data.with_csv_config(csv_config);
+        }
+        butterfly_data.fetch_csv_info().unwrap();
+
+        if let Some(report_path) = matches.value_of("csv_report") {
+            let report = butterfly_data.csv_report().unwrap();
+            if report_path.ends_with(".csv") {
+                report.store_csv(report_path).unwrap();
+            } else {
+                report.store_json(report_path).unwrap();
+            }
+        }
+    }
 
     if matches.is_present("image") {
         butterfly_data.fetch_images();
@@ -87,7 +528,49 @@ fn main() {
         butterfly_data.fetch_pdfs();
     }
 
+    if let Some(color_names_path) = matches.value_of("color_names") {
+        butterfly_data.with_color_names(ColorNames::from_path(color_names_path).unwrap());
+        butterfly_data.name_colors();
+    }
+
     if matches.is_present("dominant_colors") {
+        let mut vision_config = CloudVisionConfig::default();
+        if let Some(endpoint) = matches.value_of("vision_endpoint") {
+            vision_config.endpoint = endpoint.to_string();
+        }
+        if let Some(key_file) = matches.value_of("vision_key_file") {
+            vision_config.api_key = ApiKeySource::File(key_file.into());
+        }
+        if let Some(key_env) = matches.value_of("vision_key_env") {
+            vision_config.api_key = ApiKeySource::Env(key_env.to_string());
+        }
+        if let Some(max_results) = matches.value_of("vision_max_results") {
+            vision_config.max_results = max_results
+                .parse()
+                .expect("--vision-max-results takes a number");
+        }
+        if let Some(timeout) = matches.value_of("vision_timeout") {
+            vision_config.timeout =
+                Duration::from_secs(timeout.parse().expect("--vision-timeout takes a number"));
+        }
+        if let Some(features) = matches.values_of("vision_features") {
+            vision_config.features = features
+                .map(|feature| match feature {
+                    "labels" => VisionFeature::LabelDetection,
+                    "crop_hints" => VisionFeature::CropHints,
+                    "objects" => VisionFeature::ObjectLocalization,
+                    _ => VisionFeature::SafeSearchDetection,
+                })
+                .collect();
+        }
+        if matches.is_present("vision_image_uri") {
+            vision_config.image_mode = ImageMode::Uri;
+        }
+        butterfly_data.with_cloud_vision_config(vision_config);
+
+        if matches.value_of("color_backend") == Some("local") {
+            butterfly_data.with_color_extractor(LocalColorExtractor::default());
+        }
         butterfly_data.fetch_dominant_colors();
     }
 
@@ -1,13 +1,23 @@
+use super::butterfly_id::ButterflyId;
 use super::cloud_vision::Color;
 use super::csv_data::CSVData;
+use super::image_annotations::ImageAnnotations;
+use super::scientific_name::{ScientificName, Sex};
 use kanaria::UCSStr;
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 /// Buttterfly struct
 #[derive(Debug, PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
 pub struct Butterfly {
+    /// Identifier which stays the same across runs
+    #[serde(default)]
+    pub id: ButterflyId,
     /// Region
     pub region: String,
+    /// English name of the region, if configured
+    #[serde(default)]
+    pub eng_region: Option<String>,
     /// Category
     pub category: String,
     /// Url of an image
@@ -18,10 +28,22 @@ pub struct Butterfly {
     pub img_path: Option<String>,
     /// Path to pdf file
     pub pdf_path: String,
+    /// SHA-256 of the image file in hex
+    #[serde(default)]
+    pub img_sha256: Option<String>,
+    /// SHA-256 of the pdf file in hex
+    #[serde(default)]
+    pub pdf_sha256: Option<String>,
     /// Japanese name
     pub jp_name: String,
     /// English name
     pub eng_name: String,
+    /// Scientific name parsed from `eng_name`
+    #[serde(default)]
+    pub scientific_name: Option<ScientificName>,
+    /// Sex given by a marker such as `♀` in the names
+    #[serde(default)]
+    pub sex: Option<Sex>,
     /// Background color in 6 digit Hex
     pub bgcolor: String,
     /// Distribution
@@ -34,6 +56,12 @@ pub struct Butterfly {
     pub remarks: Option<String>,
     /// List of dominant colors
     pub dominant_colors: Vec<Color>,
+    /// List of dominant colors with the table background removed
+    #[serde(default)]
+    pub foreground_colors: Vec<Color>,
+    /// Labels, crop hints and other annotations of the image
+    #[serde(default)]
+    pub annotations: Option<ImageAnnotations>,
     /// Directory to store the assets
     pub dir_name: String,
     /// Url
@@ -54,16 +82,24 @@ impl Butterfly {
         url: &str,
     ) -> Butterfly {
         Butterfly {
+            id: ButterflyId::default(),
             region: String::from(region),
+            eng_region: None,
             category: String::from(category),
             img_src: String::from(img_src),
             pdf_src: String::from(pdf_src),
             img_path: None,
             pdf_path: String::new(),
+            img_sha256: None,
+            pdf_sha256: None,
             jp_name: String::new(),
             eng_name: String::new(),
+            scientific_name: None,
+            sex: None,
             bgcolor: String::from(bgcolor),
             dominant_colors: Vec::new(),
+            foreground_colors: Vec::new(),
+            annotations: None,
             distribution: String::new(),
             dir_name: String::from(dirname),
             url: String::from(url),
@@ -73,6 +109,16 @@ impl Butterfly {
         }
     }
 
+    /// Dominant colors with the table background removed, or the raw dominant
+    /// colors if the background has not been removed
+    pub fn palette(&self) -> &[Color] {
+        if self.foreground_colors.is_empty() {
+            &self.dominant_colors
+        } else {
+            &self.foreground_colors
+        }
+    }
+
     ///Add both English and Japanese name to given `Butterfly`
     pub(crate) fn add_names(&mut self, jp_name: &str, eng_name: &str) -> bool {
         if self.jp_name.is_empty() {
@@ -83,12 +129,26 @@ impl Butterfly {
                 .replace("\u{3000}", "");
             self.jp_name.push_str(&fixed_jp_name);
             self.eng_name.push_str(&fixed_eng_name);
+            self.sex = Sex::from_marker(&self.jp_name).or_else(|| Sex::from_marker(&self.eng_name));
+            self.scientific_name = match ScientificName::parse(&self.eng_name) {
+                Ok(scientific_name) => Some(scientific_name),
+                Err(err) => {
+                    warn!("{} of {}", err, self.jp_name);
+                    None
+                }
+            };
+            self.assign_id();
             true
         } else {
             false
         }
     }
 
+    /// Derive `id` from the names, region and sex
+    pub(crate) fn assign_id(&mut self) {
+        self.id = ButterflyId::of(self);
+    }
+
     /// Add datas from CSV file
     pub(crate) fn add_csv_data(&mut self, csv_data: &CSVData) {
         self.distribution = csv_data.distribution.to_owned();
@@ -97,3 +157,16 @@ impl Butterfly {
         self.remarks = csv_data.remarks.to_owned();
     }
 }
+
+#[cfg(test)]
+impl Butterfly {
+    /// Butterfly of `region` with given names, as if parsed from the website
+    ///
+    /// Other fields can be set with struct update syntax, except the ones
+    /// used in `id`.
+    pub(crate) fn named(region: &str, jp_name: &str, eng_name: &str) -> Butterfly {
+        let mut butterfly = Butterfly::new(region, "", "", "#ffffff", "", "", "");
+        butterfly.add_names(jp_name, eng_name);
+        butterfly
+    }
+}
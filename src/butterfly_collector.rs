@@ -6,20 +6,30 @@
 //! You should call `store_json` when all the data has been acquired.
 use kanaria::UCSStr;
 use log::{info, trace, warn};
-use reqwest::{StatusCode, Url};
+use reqwest::Url;
 use scoped_threadpool;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::fs::{create_dir_all, remove_dir_all, File};
-use std::io;
-use std::path::{Path, PathBuf};
+use std::fs::{create_dir_all, File};
+use std::path::Path;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::butterfly::Butterfly;
-use super::cloud_vision::get_dominant_colors;
+use super::butterfly_id::{disambiguate_ids, ButterflyDiff};
+use super::cloud_vision::CloudVisionConfig;
+use super::color_extractor::{CloudVision, ColorExtractor};
+use super::color_names::ColorNames;
+use super::color_space::Lab;
 use super::constants::*;
-use super::csv_data::{fetch_csv_data, CSVData, EngName, JPName};
+use super::csv_data::{fetch_csv_data, match_csv_rows, CSVData, CsvConfig, EngName, JPName};
+use super::csv_report::CsvReport;
+use super::download::download_file;
 use super::errors::ButterflyError;
+use super::http_client::HttpClient;
+use super::search_index::SearchIndex;
+use super::species::Species;
+use super::taxonomy::Taxonomy;
 use super::webpage_parser::WebpageParseResult;
 
 #[derive(Debug, Clone)]
@@ -34,21 +44,31 @@ pub struct ButterflyCollector {
     pub butterflies: Vec<Butterfly>,
     /// Pdf collection
     pub pdfs: HashSet<(String, String)>,
-    /// Datas parsed from csv file
-    pub csv_data_map: HashMap<(JPName, EngName), CSVData>,
+    /// Datas parsed from csv file, `None` until read by `fetch_csv_info`
+    pub csv_data_map: Option<HashMap<(JPName, EngName), CSVData>>,
+    /// Location and columns of the csv file, the default one if not set
+    csv_config: Option<CsvConfig>,
     /// List of region directories
     pub region_dirs: Vec<String>,
+    /// Client used to download assets and call APIs
+    http: HttpClient,
+    /// Backend used by `fetch_dominant_colors`, Cloud Vision if not set
+    color_extractor: Option<Arc<dyn ColorExtractor>>,
+    /// Config of Google Cloud Vision API
+    cloud_vision_config: CloudVisionConfig,
+    /// Color families used to name the dominant colors
+    color_names: ColorNames,
 }
 
 impl ButterflyCollector {
     /// Create new instance of `ButterflyCollector` from given `Vec<WebpageParseResult>`
     pub(crate) fn from_parse_result(
         parse_results: Vec<WebpageParseResult>,
+        http: HttpClient,
     ) -> Result<ButterflyCollector, ButterflyError> {
         let mut butterflies: Vec<Butterfly> = Vec::new();
         let mut pdfs: HashSet<(String, String)> = HashSet::new();
         let mut region_dirs: Vec<String> = Vec::new();
-        let csv_data_map = fetch_csv_data()?;
 
         for result in parse_results.into_iter() {
             let mut butterfly_vector = result
@@ -69,20 +89,233 @@ impl ButterflyCollector {
         Ok(ButterflyCollector {
             butterflies,
             pdfs,
-            csv_data_map,
+            csv_data_map: None,
+            csv_config: None,
             region_dirs,
+            http,
+            color_extractor: None,
+            cloud_vision_config: CloudVisionConfig::default(),
+            color_names: ColorNames::default(),
         })
     }
 
-    /// Fetch data from CSV data map
-    pub fn fetch_csv_info(&mut self) -> &mut Self {
+    /// Use given `HttpClient` to download assets and call APIs
+    pub fn with_http_client(&mut self, http: HttpClient) -> &mut Self {
+        self.http = http;
+        self
+    }
+
+    /// Use given endpoint and credentials when calling Google Cloud Vision API
+    ///
+    /// ```rust
+    /// butterfly_data
+    ///     .with_cloud_vision_config(CloudVisionConfig {
+    ///         endpoint: "http://localhost:8080/v1/images:annotate".to_string(),
+    ///         ..CloudVisionConfig::default()
+    ///     })
+    ///     .fetch_dominant_colors();
+    /// ```
+    pub fn with_cloud_vision_config(&mut self, config: CloudVisionConfig) -> &mut Self {
+        self.cloud_vision_config = config;
+        self
+    }
+
+    /// Name the dominant colors with given color families
+    ///
+    /// ```rust
+    /// let color_names = ColorNames::from_path("./color_names.toml").unwrap();
+    /// butterfly_data.with_color_names(color_names).name_colors();
+    /// ```
+    pub fn with_color_names(&mut self, color_names: ColorNames) -> &mut Self {
+        self.color_names = color_names;
+        self
+    }
+
+    /// Name the family of every dominant color, such as 青/blue
+    ///
+    /// Called by `fetch_dominant_colors`, so this is only needed for data
+    /// loaded from JSON file or after changing the color families.
+    pub fn name_colors(&mut self) -> &mut Self {
         for butterfly in self.butterflies.iter_mut() {
-            let jp_name = JPName(butterfly.jp_name.to_owned());
-            let eng_name = EngName(butterfly.eng_name.to_owned());
+            self.color_names.name_colors(&mut butterfly.dominant_colors);
+            self.color_names
+                .name_colors(&mut butterfly.foreground_colors);
+        }
+        self
+    }
+
+    /// Butterflies which have a dominant color of given family, using the
+    /// background-corrected palette when there is one
+    ///
+    /// `family` is either the Japanese or the English name, such as `青` or `blue`.
+    ///
+    /// ```rust
+    /// let blue_butterflies = butterfly_data.butterflies_with_color("blue");
+    /// ```
+    pub fn butterflies_with_color(&self, family: &str) -> Vec<&Butterfly> {
+        self.butterflies
+            .iter()
+            .filter(|butterfly| {
+                butterfly.palette().iter().any(|color| {
+                    color.pixel_fraction >= MIN_NAMED_COLOR_FRACTION
+                        && color.family.as_ref().map(|f| f.matches(family)) == Some(true)
+                })
+            })
+            .collect()
+    }
 
-            let csv_data = self.csv_data_map.get(&(jp_name, eng_name));
+    /// Find `k` butterflies whose colors are the closest to `hex_color`
+    ///
+    /// Butterflies are ranked by the CIEDE2000 difference between `hex_color`
+    /// and each color of their palette, weighted by the pixel fraction of the
+    /// color. Closest butterflies come first, along with the difference.
+    ///
+    /// ```rust
+    /// for (butterfly, distance) in butterfly_data.find_by_color("#1e50c8", 10).unwrap() {
+    ///     println!("{} {}", butterfly.jp_name, distance);
+    /// }
+    /// ```
+    pub fn find_by_color(
+        &self,
+        hex_color: &str,
+        k: usize,
+    ) -> Result<Vec<(&Butterfly, f32)>, ButterflyError> {
+        let query = Lab::from_hex(hex_color)
+            .ok_or_else(|| ButterflyError::InvalidColor(hex_color.to_string()))?;
+
+        let mut matches = self
+            .butterflies
+            .iter()
+            .filter_map(|butterfly| {
+                let mut weighted_distance = 0.0;
+                let mut total_weight = 0.0;
+                for color in butterfly.palette().iter() {
+                    if let Some(lab) = Lab::from_hex(&color.hex_color) {
+                        weighted_distance += color.pixel_fraction * query.ciede2000(&lab);
+                        total_weight += color.pixel_fraction;
+                    }
+                }
+                if total_weight > 0.0 {
+                    Some((butterfly, weighted_distance / total_weight))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        matches.sort_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap());
+        matches.truncate(k);
+        Ok(matches)
+    }
+
+    /// Build `SearchIndex` to search butterflies by names, distribution and
+    /// remarks
+    ///
+    /// ```rust
+    /// let index = butterfly_data.search_index();
+    /// let hits = index.search("アゲハ", 10);
+    /// ```
+    pub fn search_index(&self) -> SearchIndex<'_> {
+        SearchIndex::new(&self.butterflies)
+    }
+
+    /// Compare the butterflies with the rows of the csv file
+    ///
+    /// Lists butterflies missing from the csv file, rows which match no
+    /// butterfly and butterflies whose pdf differs from the one in the csv file.
+    ///
+    /// ```rust
+    /// let report = butterfly_data.csv_report().unwrap();
+    /// report.store_csv("./csv_report.csv").unwrap();
+    /// ```
+    pub fn csv_report(&mut self) -> Result<CsvReport, ButterflyError> {
+        let csv_data_map = load_csv_data(&mut self.csv_data_map, self.csv_config.as_ref())?;
+        Ok(CsvReport::new(&self.butterflies, csv_data_map))
+    }
+
+    /// Compare the butterflies with the ones of `old`
+    ///
+    /// ```rust
+    /// let old_data = Client::from_path("./old_butterfly.json").unwrap();
+    /// let diff = butterfly_data.diff(&old_data);
+    /// println!("{} butterflies added", diff.added.len());
+    /// ```
+    pub fn diff(&self, old: &ButterflyCollector) -> ButterflyDiff {
+        ButterflyDiff::new(&old.butterflies, &self.butterflies)
+    }
+
+    /// Group the butterflies by species, keeping every region and image
+    ///
+    /// ```rust
+    /// for species in butterfly_data.species() {
+    ///     println!("{} {:?}", species.jp_name(), species.regions());
+    /// }
+    /// ```
+    pub fn species(&self) -> Vec<Species> {
+        Species::group(&self.butterflies)
+    }
+
+    /// Build family → genus → species tree of the butterflies
+    pub fn taxonomy(&self) -> Taxonomy {
+        Taxonomy::new(&self.butterflies)
+    }
+
+    /// Build family → genus → species tree of the butterflies in given region
+    ///
+    /// ```rust
+    /// let taxonomy = butterfly_data.region_taxonomy("旧北区");
+    /// taxonomy.store_json("./old_north_taxonomy.json").unwrap();
+    /// ```
+    pub fn region_taxonomy(&self, region: &str) -> Taxonomy {
+        Taxonomy::from_region(&self.butterflies, region)
+    }
+
+    /// Use given `ColorExtractor` in `fetch_dominant_colors`
+    ///
+    /// ```rust
+    /// butterfly_data
+    ///     .with_color_extractor(LocalColorExtractor::default())
+    ///     .fetch_images()
+    ///     .fetch_dominant_colors();
+    /// ```
+    pub fn with_color_extractor<E: ColorExtractor + 'static>(&mut self, extractor: E) -> &mut Self {
+        self.color_extractor = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Read the csv file from given location and columns in `fetch_csv_info`
+    ///
+    /// ```rust
+    /// let mut csv_config = CsvConfig::default();
+    /// csv_config.path = PathBuf::from("./data/butterfly.csv");
+    /// csv_config.columns.set("remarks", "備考").unwrap();
+    /// butterfly_data.with_csv_config(csv_config).fetch_csv_info().unwrap();
+    /// ```
+    pub fn with_csv_config(&mut self, csv_config: CsvConfig) -> &mut Self {
+        self.csv_config = Some(csv_config);
+        self.csv_data_map = None;
+        self
+    }
+
+    /// Fetch data from CSV data map
+    ///
+    /// The csv file is read first if it has not been read yet. If it cannot be
+    /// read, an error is returned when the csv file was set by
+    /// `with_csv_config`, and butterflies are left as is otherwise. Rows are
+    /// matched as in `csv_data::match_csv_rows`.
+    pub fn fetch_csv_info(&mut self) -> Result<&mut Self, ButterflyError> {
+        let csv_data_map = match load_csv_data(&mut self.csv_data_map, self.csv_config.as_ref()) {
+            Ok(csv_data_map) => csv_data_map,
+            Err(err) if self.csv_config.is_none() => {
+                warn!("Skipping csv data: {}", err);
+                return Ok(self);
+            }
+            Err(err) => return Err(err),
+        };
 
-            match csv_data {
+        let keys = match_csv_rows(&self.butterflies, csv_data_map);
+        for (butterfly, key) in self.butterflies.iter_mut().zip(keys) {
+            match key.map(|key| &csv_data_map[key]) {
                 Some(additional_data) => {
                     butterfly.add_csv_data(additional_data);
                 }
@@ -92,7 +325,7 @@ impl ButterflyCollector {
             }
         }
 
-        self
+        Ok(self)
     }
 
     ///Fetch images of butterflies
@@ -106,18 +339,19 @@ impl ButterflyCollector {
 
             let img_path = Path::new(&dir_path).join(IMAGE_DIRECTORY);
 
-            if create_dir_all(&img_path).is_err() {
-                remove_dir_all(&img_path).unwrap();
-                create_dir_all(&img_path).unwrap();
+            if let Err(err) = create_dir_all(&img_path) {
+                warn!("Unable to create directory {:#?}: {}", img_path, err);
             };
         }
 
-        let mut pool = scoped_threadpool::Pool::new(100);
+        let mut pool = scoped_threadpool::Pool::new(self.http.parallelism());
+        let http = &self.http;
+        let butterflies = &mut self.butterflies;
 
         info!("Downloading image files");
 
         pool.scoped(|scope| {
-            self.butterflies.iter_mut().for_each(|butterfly| {
+            butterflies.iter_mut().for_each(|butterfly| {
                 let dir_path = Path::new(ASSET_DIRECTORY)
                     .join(&butterfly.dir_name)
                     .join(IMAGE_DIRECTORY);
@@ -130,13 +364,15 @@ impl ButterflyCollector {
                 scope.execute(move || {
                     let file_name = get_file_name(&butterfly.img_src).unwrap();
                     let file_path = dir_path.join(file_name);
-                    if let Ok(img_path) = download_file(&file_path, url) {
+                    let known_sha256 = butterfly.img_sha256.as_deref();
+                    if let Ok(download) = download_file(http, &file_path, url, known_sha256) {
                         trace!(
                             "Storing image of {} on the path {}",
                             &butterfly.jp_name,
-                            &img_path
+                            &download.path
                         );
-                        butterfly.img_path.replace(img_path);
+                        butterfly.img_path.replace(download.path);
+                        butterfly.img_sha256.replace(download.sha256);
                     } else {
                         warn!("Image could not be fetched: {}", &butterfly.jp_name);
                     };
@@ -149,32 +385,56 @@ impl ButterflyCollector {
         self
     }
 
-    /// Use Google Cloud Vision API to fetch dominant colors
+    /// Fetch dominant colors with the `ColorExtractor` set by
+    /// `with_color_extractor`, using Google Cloud Vision API by default
     pub fn fetch_dominant_colors(&mut self) -> &mut Self {
         if self.pdfs.is_empty() {
             panic!("Butterfly data has not been extracted yet!")
         }
 
-        info!("Using Google Cloud Vision to collect image property data");
+        let extractor = match &self.color_extractor {
+            Some(extractor) => extractor.clone(),
+            None => match CloudVision::new(self.http.clone(), self.cloud_vision_config.clone()) {
+                Ok(cloud_vision) => Arc::new(cloud_vision),
+                Err(err) => {
+                    warn!("Unable to use Google Cloud Vision: {}", err);
+                    return self;
+                }
+            },
+        };
+
+        info!("Using {} to collect image property data", extractor.name());
         // Use threadpool
-        let mut pool = scoped_threadpool::Pool::new(GCV_THEAD_POOL_NUM);
+        let mut pool = scoped_threadpool::Pool::new(self.http.parallelism());
+        let extractor = &extractor;
+        let butterflies = &mut self.butterflies;
 
         pool.scoped(|scoped| {
-            for butterfly in self.butterflies.iter_mut() {
-                let img_url = Url::parse(BUTTERFLY_URL)
-                    .unwrap()
-                    .join(&butterfly.img_src)
-                    .unwrap();
-
-                scoped.execute(move || match get_dominant_colors(&img_url) {
-                    Ok(colors) => {
-                        trace!("Analyzed image data of {}", butterfly.jp_name);
-                        butterfly.dominant_colors = colors;
-                    }
-                    Err(err) => {
-                        warn!("GCV request failed: {}", butterfly.jp_name);
-                        warn!("Url: {:#?}", img_url);
-                        warn!("Error: {}", err);
+            for batch in butterflies.chunks_mut(extractor.batch_size().max(1)) {
+                scoped.execute(move || {
+                    let palettes = extractor.extract_palettes(batch);
+                    for (butterfly, palette) in batch.iter_mut().zip(palettes) {
+                        match palette {
+                            Ok(palette) => {
+                                trace!("Analyzed image data of {}", butterfly.jp_name);
+                                butterfly.dominant_colors = palette.dominant_colors;
+                                butterfly.foreground_colors = palette.foreground_colors;
+                                if let Some(annotations) = palette.annotations {
+                                    if annotations.contains_butterfly() == Some(false) {
+                                        warn!(
+                                            "Image may not contain a butterfly: {} ({})",
+                                            butterfly.jp_name, butterfly.img_src
+                                        );
+                                    }
+                                    butterfly.annotations = Some(annotations);
+                                }
+                            }
+                            Err(err) => {
+                                warn!("Color extraction failed: {}", butterfly.jp_name);
+                                warn!("Image: {}", butterfly.img_src);
+                                warn!("Error: {}", err);
+                            }
+                        }
                     }
                 });
             }
@@ -182,7 +442,7 @@ impl ButterflyCollector {
 
         info!("All the images has been analyzed");
 
-        self
+        self.name_colors()
     }
 
     /// Download PDF files
@@ -196,11 +456,10 @@ impl ButterflyCollector {
         for region_dir_name in self.region_dirs.iter() {
             let dir_path = Path::new(ASSET_DIRECTORY).join(region_dir_name.to_owned());
 
-            let img_path = Path::new(&dir_path).join(PDF_DIRECTORY);
+            let pdf_path = Path::new(&dir_path).join(PDF_DIRECTORY);
 
-            if create_dir_all(&img_path).is_err() {
-                remove_dir_all(&img_path).unwrap();
-                create_dir_all(&img_path).unwrap();
+            if let Err(err) = create_dir_all(&pdf_path) {
+                warn!("Unable to create directory {:#?}: {}", pdf_path, err);
             };
         }
 
@@ -211,14 +470,21 @@ impl ButterflyCollector {
             let url = Url::parse(BUTTERFLY_URL).unwrap().join(&pdf_url).unwrap();
             let file_name = get_file_name(pdf_url).unwrap();
             let file_path = dir_path.join(file_name);
-            match download_file(&file_path, url) {
-                Ok(pdf_path) => {
+            // Butterflies sharing the pdf all record the same hash
+            let known_sha256 = self
+                .butterflies
+                .iter()
+                .find(|b| &b.pdf_src == pdf_url && b.pdf_path == file_path.to_string_lossy())
+                .and_then(|b| b.pdf_sha256.to_owned());
+            match download_file(&self.http, &file_path, url, known_sha256.as_deref()) {
+                Ok(download) => {
                     for butterfly in self.butterflies.iter_mut() {
                         if &butterfly.pdf_src == pdf_url {
-                            butterfly.pdf_path = pdf_path.to_owned();
+                            butterfly.pdf_path = download.path.to_owned();
+                            butterfly.pdf_sha256.replace(download.sha256.to_owned());
                         }
                     }
-                    trace!("Stored pdf file on: {}", pdf_path);
+                    trace!("Stored pdf file on: {}", download.path);
                 }
                 Err(err) => {
                     warn!("Unable to download pdf file: {}", err);
@@ -232,15 +498,16 @@ impl ButterflyCollector {
     }
 
     /// Store the result as JSON file as `JSON_FILE_NAME`
+    ///
+    /// Every butterfly is stored, along with `species` which groups the ones
+    /// of the same species found in several regions or with both sexes.
     pub fn store_json(&mut self, file_path: &str) -> Result<(), std::io::Error> {
         info!("Storing information into json file: {}", file_path);
 
         let butterfly_num: usize = self.butterflies.len();
         let pdf_num: usize = self.pdfs.len();
-        // Remove duplicates
         self.butterflies
-            .sort_by(|b1, b2| b1.jp_name.cmp(&b2.jp_name));
-        self.butterflies.dedup_by(|b1, b2| b1.jp_name == b2.jp_name);
+            .sort_by(|b1, b2| b1.jp_name.cmp(&b2.jp_name).then_with(|| b1.id.cmp(&b2.id)));
 
         let butterfly_json = ButterflyJSON::new(&self.butterflies, butterfly_num, pdf_num);
         let json_file = File::create(file_path)?;
@@ -250,34 +517,14 @@ impl ButterflyCollector {
     }
 }
 
-///Fetch file from biokite.com and store them on a directory
-///
-/// Will return `Error` type if,
-///
-/// 1. Image could not be fetched (either connnection issue or status code other than `Ok`)
-/// 2. Image name is unknown (very unlikely to happen)
-/// 3. File could not be created
-/// 4. Writing to file failed
-fn download_file(file_path: &PathBuf, url: Url) -> anyhow::Result<String> {
-    let mut response = reqwest::get(url)?;
-
-    if response.status() != StatusCode::OK {
-        anyhow::bail!(ButterflyError::FileNotFound(
-            file_path.to_str().unwrap().to_string(),
-        ));
-    }
-
-    let mut out = File::create(&file_path)?;
-    io::copy(&mut response, &mut out)?;
-    trace!("Downloaded: {:#?}", file_path);
-    Ok(file_path.to_str().unwrap().to_string())
-}
-
 ///Struct used to export data as JSON
 #[derive(Deserialize, Serialize, Debug, PartialEq, PartialOrd, Clone)]
 pub struct ButterflyJSON {
     /// List of butterflies
     pub butterflies: Vec<Butterfly>,
+    /// Butterflies grouped by species
+    #[serde(default)]
+    pub species: Vec<Species>,
     /// Number of butterfly data
     pub butterfly_num: usize,
     /// Number of pdf files
@@ -293,6 +540,7 @@ impl ButterflyJSON {
 
         ButterflyJSON {
             butterflies: butterflies.to_owned(),
+            species: Species::group(butterflies),
             butterfly_num,
             pdf_num,
             created_at,
@@ -301,8 +549,6 @@ impl ButterflyJSON {
 
     /// Convert itself into `ButterflyCollector`
     pub(crate) fn into_collector(self) -> Result<ButterflyCollector, ButterflyError> {
-        let csv_data_map = fetch_csv_data()?;
-
         let mut regions: HashSet<String> = HashSet::new();
         let mut butterflies: Vec<Butterfly> = Vec::new();
         let mut pdfs: HashSet<(String, String)> = HashSet::new();
@@ -310,23 +556,57 @@ impl ButterflyJSON {
         // self.pdfs
         // .insert((href.to_owned(), self.dir_name.to_owned()));
 
-        for butterfly in self.butterflies.into_iter() {
+        // JSON files stored before ids were introduced
+        let mut assigned: Vec<bool> = Vec::new();
+        for mut butterfly in self.butterflies.into_iter() {
+            assigned.push(butterfly.id.is_empty());
+            if butterfly.id.is_empty() {
+                butterfly.assign_id();
+            }
             regions.insert(butterfly.region.to_owned());
             pdfs.insert((butterfly.pdf_src.to_owned(), butterfly.dir_name.to_owned()));
             butterflies.push(butterfly.to_owned());
         }
 
+        disambiguate_ids(
+            butterflies
+                .iter_mut()
+                .zip(assigned)
+                .filter(|(_, assigned)| *assigned)
+                .map(|(butterfly, _)| butterfly),
+        );
+
         let region_dirs: Vec<String> = regions.into_iter().collect();
 
         Ok(ButterflyCollector {
             butterflies,
             pdfs,
-            csv_data_map,
+            csv_data_map: None,
+            csv_config: None,
             region_dirs,
+            http: HttpClient::default(),
+            color_extractor: None,
+            cloud_vision_config: CloudVisionConfig::default(),
+            color_names: ColorNames::default(),
         })
     }
 }
 
+/// Read the csv file given by `csv_config` unless it has been read already
+fn load_csv_data<'a>(
+    csv_data_map: &'a mut Option<HashMap<(JPName, EngName), CSVData>>,
+    csv_config: Option<&CsvConfig>,
+) -> Result<&'a HashMap<(JPName, EngName), CSVData>, ButterflyError> {
+    match csv_data_map {
+        Some(csv_data_map) => Ok(csv_data_map),
+        None => {
+            let default_config = CsvConfig::default();
+            let csv_data = fetch_csv_data(csv_config.unwrap_or(&default_config))?;
+            Ok(csv_data_map.insert(csv_data))
+        }
+    }
+}
+
 /// Return current POSIX time
 fn now() -> u64 {
     let start = SystemTime::now();
@@ -343,3 +623,115 @@ fn get_file_name(url_path: &str) -> Option<String> {
         .last()
         .map(|name| UCSStr::from_str(name).narrow().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butterfly_id::ButterflyId;
+    use crate::cloud_vision::Color;
+
+    fn collector(palettes: &[(&str, &[(&str, f32)])]) -> ButterflyCollector {
+        let color_names = ColorNames::default();
+        let butterflies = palettes
+            .iter()
+            .map(|(jp_name, palette)| {
+                let mut butterfly = Butterfly::new("", "", "", "#ffffff", "", "", "");
+                butterfly.jp_name = jp_name.to_string();
+                butterfly.dominant_colors = palette
+                    .iter()
+                    .map(|(hex, pixel_fraction)| Color {
+                        pixel_fraction: *pixel_fraction,
+                        score: *pixel_fraction,
+                        hex_color: hex.to_string(),
+                        family: color_names.classify(hex),
+                    })
+                    .collect();
+                butterfly
+            })
+            .collect();
+
+        ButterflyCollector {
+            butterflies,
+            pdfs: HashSet::new(),
+            csv_data_map: None,
+            csv_config: None,
+            region_dirs: Vec::new(),
+            http: HttpClient::default(),
+            color_extractor: None,
+            cloud_vision_config: CloudVisionConfig::default(),
+            color_names,
+        }
+    }
+
+    #[test]
+    fn ids_of_old_json_files_are_told_apart_like_parsed_ones() {
+        let butterfly = |img_src: &str| Butterfly {
+            id: ButterflyId::default(),
+            img_src: img_src.to_string(),
+            ..Butterfly::named("旧北区", "キアゲハ", "Papilio_machaon")
+        };
+        let json = ButterflyJSON::new(
+            &[
+                butterfly("butterfly_img/p/machaon.jpg"),
+                butterfly("butterfly_img/p/machaon_2.jpg"),
+            ],
+            2,
+            1,
+        );
+
+        let butterflies = json.into_collector().unwrap().butterflies;
+        let id = ButterflyId::of(&butterflies[0]);
+        assert_eq!(butterflies[0].id, id);
+        assert_eq!(
+            butterflies[1].id,
+            id.with_image("butterfly_img/p/machaon_2.jpg")
+        );
+    }
+
+    #[test]
+    fn csv_file_set_explicitly_must_be_read() {
+        let mut collector = collector(&[("キアゲハ", &[])]);
+        let csv_config = CsvConfig {
+            path: "./tests/missing.csv".into(),
+            ..CsvConfig::default()
+        };
+
+        assert!(collector
+            .with_csv_config(csv_config)
+            .fetch_csv_info()
+            .is_err());
+        assert!(collector.csv_data_map.is_none());
+        assert!(collector.csv_report().is_err());
+    }
+
+    #[test]
+    fn butterflies_are_ranked_by_color_difference() {
+        let collector = collector(&[
+            ("キアゲハ", &[("#f0d000", 0.6), ("#101010", 0.4)]),
+            ("モルフォチョウ", &[("#1e50c8", 0.8), ("#101010", 0.2)]),
+            ("ミドリシジミ", &[("#20a060", 0.7), ("#603010", 0.3)]),
+            ("シロチョウ", &[]),
+        ]);
+
+        let matches = collector.find_by_color("#2050d0", 2).unwrap();
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].0.jp_name, "モルフォチョウ");
+        assert!(matches[0].1 < matches[1].1);
+        assert!(collector.find_by_color("blue", 2).is_err());
+    }
+
+    #[test]
+    fn butterflies_are_found_by_color_family() {
+        let collector = collector(&[
+            ("キアゲハ", &[("#f0d000", 0.6), ("#101010", 0.4)]),
+            ("モルフォチョウ", &[("#1e50c8", 0.97), ("#f0d000", 0.03)]),
+        ]);
+
+        let yellow = collector.butterflies_with_color("黄");
+
+        assert_eq!(yellow.len(), 1);
+        assert_eq!(yellow[0].jp_name, "キアゲハ");
+        assert_eq!(collector.butterflies_with_color("Blue").len(), 1);
+    }
+}
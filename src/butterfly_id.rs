@@ -0,0 +1,302 @@
+//! # Butterfly Id
+//!
+//! This module exports `ButterflyId`, an identifier of a butterfly which stays
+//! the same across runs, and `ButterflyDiff`, which compares two collections of
+//! butterflies using it.
+//!
+//! The id is derived from the normalized scientific name, the Japanese name,
+//! the region and the sex of the butterfly, so the order of the cells on the
+//! website does not matter. The Japanese name tells apart butterflies of the
+//! same species, such as the underside of the wings. Butterflies which still
+//! share an id are told apart by the file name of their image, except for the
+//! first one so its id does not change when another image is added.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use super::butterfly::Butterfly;
+use super::download::sha256_bytes;
+use super::scientific_name::{strip_sex_markers, ScientificName, Sex};
+
+// Number of hex digits of SHA-256 kept in the id
+const ID_LENGTH: usize = 16;
+
+/// Identifier of a butterfly which is stable across runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ButterflyId(String);
+
+impl ButterflyId {
+    /// Create an new instance of `ButterflyId`
+    ///
+    /// `name_key` identifies the species, and `jp_name` tells apart
+    /// butterflies of the same species.
+    pub fn new(name_key: &str, jp_name: &str, region: &str, sex: Option<Sex>) -> ButterflyId {
+        let sex = match sex {
+            Some(Sex::Female) => "female",
+            Some(Sex::Male) => "male",
+            None => "",
+        };
+        ButterflyId::hash(&[name_key, &normalize_id_name(jp_name), region.trim(), sex])
+    }
+
+    /// Create `ButterflyId` of given `Butterfly`
+    pub fn of(butterfly: &Butterfly) -> ButterflyId {
+        let name_key = name_key(
+            butterfly.scientific_name.as_ref(),
+            &butterfly.eng_name,
+            &butterfly.jp_name,
+        );
+        ButterflyId::new(
+            &name_key,
+            &butterfly.jp_name,
+            &butterfly.region,
+            butterfly.sex,
+        )
+    }
+
+    /// Id told apart by the file name of the image `img_src`, for butterflies
+    /// whose ids collide otherwise
+    pub(crate) fn with_image(&self, img_src: &str) -> ButterflyId {
+        let file_name = img_src.rsplit('/').next().unwrap_or(img_src);
+        ButterflyId::hash(&[&self.0, file_name])
+    }
+
+    fn hash(parts: &[&str]) -> ButterflyId {
+        let mut hash = sha256_bytes(parts.join("\u{1f}").as_bytes());
+        hash.truncate(ID_LENGTH);
+        ButterflyId(hash)
+    }
+
+    /// Id as string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Check if the id has not been assigned
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for ButterflyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Normalized name which identifies the species of a butterfly
+///
+/// The scientific name is used when there is one, falling back to `eng_name`
+/// and then to `jp_name`, both without sex markers.
+pub(crate) fn name_key(
+    scientific_name: Option<&ScientificName>,
+    eng_name: &str,
+    jp_name: &str,
+) -> String {
+    if let Some(scientific_name) = scientific_name {
+        return scientific_name.to_string().to_lowercase();
+    }
+
+    if normalize_id_name(eng_name).is_empty() {
+        normalize_id_name(jp_name)
+    } else {
+        normalize_id_name(eng_name)
+    }
+}
+
+/// Normalization of the names used in ids
+///
+/// Ids are compared across runs, so this must never change. Sex markers are
+/// removed, full-width ASCII is narrowed, hiragana is turned into katakana,
+/// ASCII is lowercased, and runs of whitespace and `_` become a single space.
+pub(crate) fn normalize_id_name(name: &str) -> String {
+    strip_sex_markers(name)
+        .chars()
+        .map(|c| match c {
+            '\u{3000}' | '_' => ' ',
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+            '\u{3041}'..='\u{3096}' => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+            _ => c,
+        })
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Tell apart `butterflies` sharing an id by the file name of their image
+///
+/// Butterflies are given in the order they appear on the page, and the first
+/// one of those sharing an id keeps it.
+pub(crate) fn disambiguate_ids<'a, I>(butterflies: I)
+where
+    I: IntoIterator<Item = &'a mut Butterfly>,
+{
+    let mut by_id: BTreeMap<ButterflyId, Vec<&mut Butterfly>> = BTreeMap::new();
+    for butterfly in butterflies.into_iter() {
+        by_id
+            .entry(butterfly.id.to_owned())
+            .or_default()
+            .push(butterfly);
+    }
+
+    for (id, butterflies) in by_id.into_iter() {
+        if butterflies.len() < 2 {
+            continue;
+        }
+        warn!(
+            "{} butterflies share the id {} in {}, telling them apart by image",
+            butterflies.len(),
+            id,
+            butterflies[0].region
+        );
+        for butterfly in butterflies.into_iter().skip(1) {
+            butterfly.id = id.with_image(&butterfly.img_src);
+        }
+    }
+}
+
+/// Difference between two collections of butterflies, matched by `ButterflyId`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ButterflyDiff {
+    /// Butterflies only in the new collection
+    pub added: Vec<ButterflyId>,
+    /// Butterflies only in the old collection
+    pub removed: Vec<ButterflyId>,
+    /// Butterflies in both collections whose data differ
+    pub changed: Vec<ButterflyId>,
+}
+
+impl ButterflyDiff {
+    /// Compare `old` and `new`
+    ///
+    /// Butterflies sharing an id, as in files stored by older versions, are
+    /// matched in the order they appear.
+    pub fn new(old: &[Butterfly], new: &[Butterfly]) -> ButterflyDiff {
+        let old_map = group_by_id(old);
+        let new_map = group_by_id(new);
+
+        let mut diff = ButterflyDiff::default();
+        for (id, new_butterflies) in new_map.iter() {
+            let old_butterflies = old_map.get(id).map(|b| b.as_slice()).unwrap_or(&[]);
+            for i in 0..new_butterflies.len().max(old_butterflies.len()) {
+                match (old_butterflies.get(i), new_butterflies.get(i)) {
+                    (Some(old), Some(new)) if old != new => diff.changed.push((*id).to_owned()),
+                    (None, Some(_)) => diff.added.push((*id).to_owned()),
+                    (Some(_), None) => diff.removed.push((*id).to_owned()),
+                    _ => {}
+                }
+            }
+        }
+        for (id, old_butterflies) in old_map.iter() {
+            if !new_map.contains_key(id) {
+                for _ in old_butterflies.iter() {
+                    diff.removed.push((*id).to_owned());
+                }
+            }
+        }
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff
+    }
+
+    /// Check if both collections are the same
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn group_by_id(butterflies: &[Butterfly]) -> HashMap<&ButterflyId, Vec<&Butterfly>> {
+    let mut map: HashMap<&ButterflyId, Vec<&Butterfly>> = HashMap::new();
+    for butterfly in butterflies.iter() {
+        map.entry(&butterfly.id).or_default().push(butterfly);
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_depend_on_name_region_and_sex() {
+        let female = Butterfly::named("旧北区", "ツマムラサキマダラ♀", "Euploea_mulcibe");
+        let male = Butterfly::named("旧北区", "ツマムラサキマダラ♂", "Euploea_mulcibe");
+        let other_region = Butterfly::named("新北区", "ツマムラサキマダラ♀", "Euploea_mulcibe");
+        let same = Butterfly::named("旧北区", "ツマムラサキマダラ♀", "Euploea mulcibe");
+
+        assert_eq!(female.id.as_str().len(), ID_LENGTH);
+        assert_eq!(female.id, same.id);
+        assert_eq!(female.id, ButterflyId::of(&female));
+        assert_ne!(female.id, male.id);
+        assert_ne!(female.id, other_region.id);
+    }
+
+    #[test]
+    fn butterflies_of_same_species_have_distinct_ids() {
+        let upper = Butterfly::named("旧北区", "キアゲハ", "Papilio_machaon");
+        let under = Butterfly::named("旧北区", "キアゲハ裏", "Papilio_machaon");
+
+        assert_ne!(upper.id, under.id);
+        assert_ne!(upper.id.with_image("a.jpg"), upper.id.with_image("b.jpg"));
+        assert_eq!(
+            upper.id.with_image("butterfly_img/a.jpg"),
+            upper.id.with_image("a.jpg")
+        );
+    }
+
+    #[test]
+    fn ids_are_stable() {
+        // Changing this value breaks the ids stored by previous runs
+        let id = ButterflyId::new(
+            "euploea mulcibe",
+            "ツマムラサキマダラ♀",
+            "旧北区",
+            Some(Sex::Female),
+        );
+        assert_eq!(id.as_str(), "21453c3771c19560");
+        assert_eq!(
+            normalize_id_name("Ｅｕｐｌｏｅａ__ｓｐ.　つまむらさき♀"),
+            "euploea sp. ツマムラサキ"
+        );
+    }
+
+    #[test]
+    fn name_key_falls_back_to_raw_names() {
+        assert_eq!(name_key(None, "Euploea_sp.♀", ""), "euploea sp.");
+        assert_eq!(
+            name_key(None, "", "ツマムラサキマダラ♀"),
+            "ツマムラサキマダラ"
+        );
+    }
+
+    #[test]
+    fn collections_are_compared_by_id() {
+        let old = vec![
+            Butterfly::named("旧北区", "キアゲハ", "Papilio_machaon"),
+            Butterfly::named("旧北区", "ナミアゲハ", "Papilio_xuthus"),
+        ];
+        let mut new = vec![
+            Butterfly::named("旧北区", "キアゲハ", "Papilio_machaon"),
+            Butterfly::named("新北区", "キアゲハ", "Papilio_machaon"),
+        ];
+        new[0].open_length = 90;
+
+        let diff = ButterflyDiff::new(&old, &new);
+        assert_eq!(diff.added, vec![new[1].id.to_owned()]);
+        assert_eq!(diff.removed, vec![old[1].id.to_owned()]);
+        assert_eq!(diff.changed, vec![old[0].id.to_owned()]);
+        assert!(ButterflyDiff::new(&old, &old).is_empty());
+
+        let duplicated = vec![old[0].clone(), old[0].clone()];
+        let diff = ButterflyDiff::new(&old[..1], &duplicated);
+        assert_eq!(diff.added, vec![old[0].id.to_owned()]);
+        assert!(diff.changed.is_empty());
+    }
+}
@@ -1,10 +1,19 @@
-use log::info;
+use log::{info, warn};
+use rayon::prelude::*;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
+use std::path::Path;
 
 use super::butterfly_collector::{ButterflyCollector, ButterflyJSON};
+use super::constants::*;
 use super::errors::ButterflyError::{self, *};
-use super::webpage_parser::WebpageParser;
+use super::http_client::HttpClient;
+use super::name_overrides::{NameOverrideReport, NameOverrides};
+use super::page_source::PageSource;
+use super::region_config::RegionCatalogue;
+use super::region_discovery::{discover_regions, DiscoveredRegion};
+use super::webpage_parser::{ParseOptions, ParseReport, WebpageParser};
 
 /// Client used to retrieve butterfly data
 ///
@@ -13,6 +22,25 @@ use super::webpage_parser::WebpageParser;
 /// You can also retrieve data from JSON file with `from_path`
 pub struct Client {
     targets: Vec<WebpageParser>,
+    catalogue: RegionCatalogue,
+    name_overrides: NameOverrides,
+    used_name_overrides: HashMap<String, usize>,
+    parse_options: ParseOptions,
+    parse_report: ParseReport,
+    concurrency: usize,
+    region_errors: Vec<RegionError>,
+    http: HttpClient,
+}
+
+/// Error which occurred while extracting data from a region
+#[derive(Debug)]
+pub struct RegionError {
+    /// Name of the region
+    pub region: String,
+    /// Url of region page
+    pub url: String,
+    /// Error that occurred
+    pub error: ButterflyError,
 }
 
 impl Client {
@@ -25,30 +53,165 @@ impl Client {
     ///        "旧北区",
     ///        "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall",
     ///    ),
-    ///    WebpageParser::new(
-    ///        "new_north",
-    ///        "新北区",
-    ///        "http://biokite.com/worldbutterfly/butterfly-NArc.htm#NAsa",
-    ///    ),
-    ///    WebpageParser::new(
-    ///        "new_tropical",
-    ///        "新熱帯区",
-    ///        "http://biokite.com/worldbutterfly/butterfly-NTro.htm#NTmap",
-    ///    ),
-    ///    WebpageParser::new(
-    ///        "india_australia",
-    ///        "インド・オーストラリア区",
-    ///        "http://biokite.com/worldbutterfly/butterfly-IOrs.htm#IOmap",
-    ///    ),
-    ///    WebpageParser::new(
-    ///        "tropical_africa",
-    ///        "熱帯アフリカ区",
-    ///        "http://biokite.com/worldbutterfly/butterfly-TAfr.htm#TAmaps",
-    ///    ),
     ///]);
     ///```
     pub fn new(targets: Vec<WebpageParser>) -> Client {
-        Client { targets }
+        Client {
+            targets,
+            catalogue: RegionCatalogue::default(),
+            name_overrides: NameOverrides::default(),
+            used_name_overrides: HashMap::new(),
+            parse_options: ParseOptions::strict(),
+            parse_report: ParseReport::default(),
+            concurrency: REGION_THREAD_POOL_NUM,
+            region_errors: Vec::new(),
+            http: HttpClient::default(),
+        }
+    }
+
+    /// Use given `HttpClient` for every request, including the ones made by
+    /// the `ButterflyCollector` returned from `collect_datas`
+    ///
+    ///```rust
+    /// let http = HttpClient::new(HttpConfig {
+    ///     retries: 5,
+    ///     ..HttpConfig::default()
+    /// })
+    /// .unwrap();
+    /// let mut client = Client::new(targets).with_http_client(http);
+    ///```
+    pub fn with_http_client(mut self, http: HttpClient) -> Client {
+        self.http = http;
+        self
+    }
+
+    /// Set the number of regions fetched and parsed at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Client {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Decide how strictly the region pages are parsed
+    ///
+    /// By default, the first bad cell aborts the region. With lenient options
+    /// bad cells are skipped and listed in `parse_report`.
+    ///
+    ///```rust
+    /// let mut client = Client::new(targets).with_parse_options(ParseOptions::lenient(10));
+    ///```
+    pub fn with_parse_options(mut self, parse_options: ParseOptions) -> Client {
+        self.parse_options = parse_options;
+        self
+    }
+
+    /// Correct the names of irregular cells with given `NameOverrides`
+    ///
+    /// The overrides are tried before the ones bundled into the crate, which
+    /// are always used.
+    ///
+    ///```rust
+    /// let overrides = NameOverrides::from_path("./my_name_overrides.json").unwrap();
+    /// let mut client = Client::new(targets).with_name_overrides(overrides);
+    ///```
+    pub fn with_name_overrides(mut self, name_overrides: NameOverrides) -> Client {
+        self.name_overrides.layer(name_overrides);
+        self
+    }
+
+    /// Create an new instance of `Client` with the enabled regions listed in
+    /// the TOML file on `config_path`
+    ///
+    ///```rust
+    /// let mut client = Client::from_config("./regions.toml").unwrap();
+    ///```
+    pub fn from_config<P: AsRef<Path>>(config_path: P) -> Result<Client, ButterflyError> {
+        let catalogue = RegionCatalogue::from_path(config_path)?;
+        Ok(Client::from_catalogue(catalogue))
+    }
+
+    /// Create an new instance of `Client` with the enabled regions of
+    /// `catalogue`
+    ///
+    /// Disabled regions are not extracted, but are remembered so that
+    /// `with_discovered_regions` does not add them back.
+    pub fn from_catalogue(catalogue: RegionCatalogue) -> Client {
+        let mut client = Client::new(catalogue.parsers());
+        client.catalogue = catalogue;
+        client
+    }
+
+    /// Add the region pages linked from the index page read from `index`
+    ///
+    /// Warns about regions which appeared on the index page and are not known
+    /// yet, as well as known regions which are no longer linked from it. The
+    /// latter are kept as targets. Regions listed in the catalogue, including
+    /// disabled ones and ones pointed at mirrors, are never added.
+    ///
+    ///```rust
+    /// let mut client = Client::from_config("./regions.toml")
+    ///     .unwrap()
+    ///     .with_discovered_regions(&PageSource::Http(INDEX_URL.to_string()))
+    ///     .unwrap();
+    ///```
+    pub fn with_discovered_regions(mut self, index: &PageSource) -> Result<Client, ButterflyError> {
+        let discovered = discover_regions(index, &self.http)?;
+
+        for target in unlinked_targets(&self.targets, &discovered) {
+            warn!(
+                "Region is no longer linked from the index page: {} ({})",
+                target.region, target.url
+            );
+        }
+
+        for region in discovered.iter() {
+            let is_target = self
+                .targets
+                .iter()
+                .any(|t| region.is_page_of(&t.url, &t.dir_name));
+            if is_target {
+                continue;
+            }
+            if self
+                .catalogue
+                .regions
+                .iter()
+                .any(|config| region.is_page_of(&config.url, &config.dir_name))
+            {
+                info!(
+                    "Region is already in the catalogue: {} ({})",
+                    region.name, region.url
+                );
+                continue;
+            }
+            warn!("New region found: {} ({})", region.name, region.url);
+            self.targets.push(region.to_parser());
+        }
+
+        Ok(self)
+    }
+
+    /// Read the region pages saved under `dir` instead of fetching them
+    ///
+    /// Each page is expected to be stored as `<dir_name>.htm`, encoded in
+    /// Shift-JIS as it is served on the website.
+    ///
+    ///```rust
+    /// let mut client = Client::new(vec![
+    ///    WebpageParser::new(
+    ///        "old_north",
+    ///        "旧北区",
+    ///        "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall",
+    ///    )])
+    ///    .with_page_dir("./snapshots");
+    /// // Reads ./snapshots/old_north.htm
+    /// let result = client.collect_datas().unwrap();
+    ///```
+    pub fn with_page_dir<P: AsRef<Path>>(mut self, dir: P) -> Client {
+        for target in self.targets.iter_mut() {
+            let path = dir.as_ref().join(format!("{}.htm", target.dir_name));
+            target.source = PageSource::File(path);
+        }
+        self
     }
 
     /// Collect datas from butterfly website
@@ -62,19 +225,85 @@ impl Client {
     ///    )]);
     /// let result = client.collect_datas.unwrap();
     ///```
+    ///
+    /// Regions are fetched in parallel. A region which fails does not abort the
+    /// others; its error is kept in `region_errors`. Fails only when no region
+    /// could be extracted. `region_errors`, `parse_report` and
+    /// `name_override_report` describe the latest call only.
     pub fn collect_datas(&mut self) -> Result<ButterflyCollector, ButterflyError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.concurrency)
+            .build()
+            .map_err(|e| FailedToBuildThreadPool(e.to_string()))?;
+
+        let targets = &mut self.targets;
+        let http = &self.http;
+        let name_overrides = &self.name_overrides;
+        let parse_options = &self.parse_options;
+        let outcomes = pool.install(|| {
+            targets
+                .par_iter_mut()
+                .map(|target| {
+                    info!("Extracting data from: {}", &target.region);
+                    let outcome = target.fetch_data(http, name_overrides, parse_options);
+                    if outcome.is_ok() {
+                        info!("Finished extracting data from: {}", &target.region);
+                    }
+                    (target.region.to_owned(), target.url.to_owned(), outcome)
+                })
+                .collect::<Vec<_>>()
+        });
+
         let mut results = Vec::new();
+        self.region_errors.clear();
+        self.parse_report = ParseReport::default();
+        self.used_name_overrides.clear();
 
-        for target in self.targets.iter_mut() {
-            info!("Extracting data from: {}", &target.region);
-            let result = target
-                .fetch_data()
-                .map_err(|_| FailedToFetchHTML(target.url.clone()))?;
-            results.push(result.to_owned());
-            info!("Finished extracting data from: {}", &target.region);
+        for (region, url, outcome) in outcomes.into_iter() {
+            let result = match outcome {
+                Ok(result) => result,
+                Err(error) => {
+                    warn!("Failed to extract data from {}: {}", region, error);
+                    self.region_errors.push(RegionError { region, url, error });
+                    continue;
+                }
+            };
+            if !result.report.is_empty() {
+                warn!("Skipped {} cells in {}", result.report.len(), &region);
+                self.parse_report.merge(&result.report);
+            }
+            for (raw, count) in result.used_name_overrides.iter() {
+                *self.used_name_overrides.entry(raw.to_owned()).or_insert(0) += count;
+            }
+            results.push(result);
+        }
+
+        for raw in self.name_override_report().unused.iter() {
+            warn!("Name override was never used: {}", raw);
         }
 
-        ButterflyCollector::from_parse_result(results)
+        if results.is_empty() && !self.region_errors.is_empty() {
+            return Err(NoRegionExtracted(self.region_errors.len()));
+        }
+
+        ButterflyCollector::from_parse_result(results, self.http.clone())
+    }
+
+    /// Regions which `collect_datas` failed to extract
+    pub fn region_errors(&self) -> &[RegionError] {
+        &self.region_errors
+    }
+
+    /// Cells skipped by `collect_datas` when parsing leniently
+    pub fn parse_report(&self) -> &ParseReport {
+        &self.parse_report
+    }
+
+    /// Report which name overrides were used by `collect_datas`
+    ///
+    /// Overrides that were never used can be pruned from the override file.
+    pub fn name_override_report(&self) -> NameOverrideReport {
+        self.name_overrides.report(&self.used_name_overrides)
     }
 
     /// Retrieve data from JSON file
@@ -95,3 +324,123 @@ impl Client {
         butterfly_json.into_collector()
     }
 }
+
+/// Targets which are not any of the regions linked from the index page
+fn unlinked_targets<'a>(
+    targets: &'a [WebpageParser],
+    discovered: &[DiscoveredRegion],
+) -> Vec<&'a WebpageParser> {
+    targets
+        .iter()
+        .filter(|target| {
+            !discovered
+                .iter()
+                .any(|region| region.is_page_of(&target.url, &target.dir_name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region_config::RegionConfig;
+
+    const FIXTURE_DIRECTORY: &str = "tests/fixtures/region_pages";
+
+    const INDEX_HTML: &str = r#"<html><body>
+        <a href="butterfly-PArc.htm#PAall">旧北区</a>
+        <a href="butterfly-NArc.htm#NAsa">新北区</a>
+        <a href="butterfly-TAfr.htm#TAmaps">熱帯アフリカ区</a>
+        </body></html>"#;
+
+    fn region(dir_name: &str, url: &str, enabled: bool) -> RegionConfig {
+        RegionConfig {
+            dir_name: dir_name.to_string(),
+            name: String::new(),
+            url: url.to_string(),
+            eng_name: None,
+            enabled,
+        }
+    }
+
+    #[test]
+    fn catalogued_regions_are_not_discovered_again() {
+        let catalogue = RegionCatalogue {
+            regions: vec![
+                region(
+                    "old_north",
+                    "http://mirror.example.com/butterfly-PArc.htm",
+                    true,
+                ),
+                region(
+                    "new_north",
+                    "http://biokite.com/worldbutterfly/butterfly-NArc.htm#NAsa",
+                    false,
+                ),
+            ],
+        };
+        let client = Client::from_catalogue(catalogue)
+            .with_discovered_regions(&PageSource::Text(INDEX_HTML.to_string()))
+            .unwrap();
+        let dir_names = client
+            .targets
+            .iter()
+            .map(|t| t.dir_name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(dir_names, vec!["old_north", "tafr"]);
+
+        // Region pointed at the mirror is still linked from the index page
+        let discovered = discover_regions(
+            &PageSource::Text(INDEX_HTML.to_string()),
+            &HttpClient::default(),
+        )
+        .unwrap();
+        assert!(unlinked_targets(&client.targets, &discovered).is_empty());
+        assert_eq!(unlinked_targets(&client.targets, &discovered[1..]).len(), 1);
+    }
+
+    // Region whose page is given as text, read from a Shift-JIS fixture
+    fn fixture_target(dir_name: &str, fixture: &str) -> WebpageParser {
+        let path = Path::new(FIXTURE_DIRECTORY).join(format!("{}.htm", fixture));
+        let html = PageSource::file(path).read(&HttpClient::default()).unwrap();
+        WebpageParser::new(
+            dir_name,
+            dir_name,
+            &format!(
+                "http://biokite.com/worldbutterfly/butterfly-{}.htm",
+                dir_name
+            ),
+        )
+        .with_source(PageSource::Text(html))
+    }
+
+    #[test]
+    fn failed_regions_do_not_abort_the_others() {
+        let mut client = Client::new(vec![
+            fixture_target("categories", "categories"),
+            fixture_target("malformed", "malformed"),
+            fixture_target("missing_link", "missing_link"),
+        ])
+        .with_parse_options(ParseOptions::lenient(2));
+
+        for _ in 0..2 {
+            let collector = client.collect_datas().unwrap();
+            assert_eq!(collector.region_dirs, vec!["categories", "missing_link"]);
+
+            let errors = client.region_errors();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].region, "malformed");
+
+            // Reports are not accumulated across calls
+            assert_eq!(client.parse_report().len(), 1);
+            assert_eq!(
+                client.name_override_report().used,
+                vec![
+                    ("ヒメアカタテハCynthia_cardui".to_string(), 1),
+                    ("ツマムラサキマダラ♀Euploea_mulcibe".to_string(), 1),
+                ]
+            );
+        }
+    }
+}
@@ -4,61 +4,248 @@ use hex;
 use reqwest::{StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::env;
 use std::fmt;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use super::color_names::ColorName;
 use super::constants::*;
+use super::errors::ButterflyError;
+use super::http_client::{HttpClient, HttpConfig};
+use super::image_annotations::VisionFeature;
+
+/// Maximum number of images in a single `images:annotate` request
+pub(crate) const MAX_BATCH_SIZE: usize = 16;
+
+/// Where the api key of Google Cloud Vision API is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeySource {
+    /// Read the key from given file
+    File(PathBuf),
+    /// Read the key from given environment variable
+    Env(String),
+}
+
+impl ApiKeySource {
+    /// Read the api key
+    fn load(&self) -> Result<ApiKey, ButterflyError> {
+        let key = match self {
+            ApiKeySource::File(path) => fs::read_to_string(path)
+                .map_err(|_e| ButterflyError::ApiKeyNotFound(path.display().to_string()))?,
+            ApiKeySource::Env(name) => {
+                env::var(name).map_err(|_e| ButterflyError::ApiKeyNotFound(format!("${}", name)))?
+            }
+        };
+        Ok(ApiKey(key.trim().to_string()))
+    }
+}
+
+/// Api key, hidden from debug output
+#[derive(Clone, PartialEq)]
+struct ApiKey(String);
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKey(***)")
+    }
+}
+
+/// How images are given to Google Cloud Vision API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageMode {
+    /// Upload the image, read from `img_path` when it has been downloaded
+    Content,
+    /// Send the url of the image and let the API fetch it
+    Uri,
+}
+
+/// Image sent to Google Cloud Vision API
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum VisionImage {
+    /// Content of the image
+    Content(Arc<Vec<u8>>),
+    /// Url of the image
+    Uri(String),
+}
 
-/// Get list of `Color` using Google Cloud Vision API
-pub(crate) fn get_dominant_colors(image_url: &Url) -> Result<Vec<Color>, CloudVisionError> {
-    let response_json = use_cloud_vision_api(image_url)?;
-    let extracted_color_vec = extract_colors(&response_json)?;
+/// Endpoint, credentials and request options of Google Cloud Vision API
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloudVisionConfig {
+    /// Url of `images:annotate` endpoint
+    pub endpoint: String,
+    /// Where the api key is read from
+    pub api_key: ApiKeySource,
+    /// Maximum number of colors returned for each image
+    pub max_results: u32,
+    /// Timeout of a single request
+    pub timeout: Duration,
+    /// How images are given to the API
+    pub image_mode: ImageMode,
+    /// Features requested in addition to the dominant colors
+    pub features: Vec<VisionFeature>,
+}
 
-    if extracted_color_vec.is_empty() {
-        return Err(VectorIsEmpty);
+impl Default for CloudVisionConfig {
+    fn default() -> Self {
+        CloudVisionConfig {
+            endpoint: CLOUD_VISION_URI.to_string(),
+            api_key: ApiKeySource::File(PathBuf::from(API_KEY_FILE_PATH)),
+            max_results: 10,
+            timeout: Duration::from_secs(60),
+            image_mode: ImageMode::Content,
+            features: Vec::new(),
+        }
     }
+}
 
-    Ok(extracted_color_vec)
+/// Client of Google Cloud Vision API
+#[derive(Debug, Clone)]
+pub(crate) struct VisionClient {
+    /// Client used to fetch images
+    pub(crate) http: HttpClient,
+    // Client used to call the api, with the timeout of the config
+    api_http: HttpClient,
+    config: CloudVisionConfig,
+    // Read on the first request, so cached responses can be used without it
+    api_key: Arc<Mutex<Option<ApiKey>>>,
 }
 
-/// Use cloud vision api
-fn use_cloud_vision_api(image_url: &Url) -> Result<Value, CloudVisionError> {
-    let base64_image =
-        get_base64_image(image_url).ok_or_else(|| UnableToFetchImage(image_url.to_owned()))?;
+impl VisionClient {
+    /// Create an new instance of `VisionClient`
+    ///
+    /// The api key is read once, when the first request is sent.
+    pub(crate) fn new(
+        http: HttpClient,
+        config: CloudVisionConfig,
+    ) -> Result<VisionClient, ButterflyError> {
+        let api_http = HttpClient::new(HttpConfig {
+            timeout: config.timeout,
+            ..http.config().clone()
+        })?;
+
+        Ok(VisionClient {
+            http,
+            api_http,
+            config,
+            api_key: Arc::new(Mutex::new(None)),
+        })
+    }
 
-    let request = json!({
-        "requests": [
-          {
-            "image": {
-                "content": base64_image
-            },
-            "features": [
-              {
-                "maxResults": 10,
-                "type": "IMAGE_PROPERTIES"
-              }
-            ]
-          }
-        ]
-    });
+    /// Api key, read from the source given in the config on first use
+    fn api_key(&self) -> Result<ApiKey, CloudVisionError> {
+        let mut api_key = self.api_key.lock().unwrap();
+        if let Some(api_key) = api_key.as_ref() {
+            return Ok(api_key.clone());
+        }
+        let loaded = self
+            .config
+            .api_key
+            .load()
+            .map_err(|err| MissingApiKey(err.to_string()))?;
+        *api_key = Some(loaded.clone());
+        Ok(loaded)
+    }
 
-    let secret_key = fs::read_to_string(API_KEY_FILE_PATH)
-        .map_err(|_| KeyFileNotFound(API_KEY_FILE_PATH.to_owned()))?;
+    /// How images are given to the API
+    pub(crate) fn image_mode(&self) -> ImageMode {
+        self.config.image_mode
+    }
 
-    let mut response = reqwest::Client::new()
-        .post(CLOUD_VISION_URI)
-        .query(&[("key", secret_key)])
-        .json(&request)
-        .send()
-        .map_err(|_err| FailedGCV)?;
+    /// Optional features requested with the dominant colors
+    pub(crate) fn features(&self) -> &[VisionFeature] {
+        &self.config.features
+    }
 
-    if response.status() != StatusCode::OK {
-        return Err(BadRequest(image_url.to_owned()));
+    /// Key of the cached response of the image with given hash
+    ///
+    /// Responses requested with other features or another `max_results` are
+    /// cached separately. The order of the features does not matter.
+    pub(crate) fn cache_key(&self, sha256: &str) -> String {
+        let mut key = format!("{}-max{}", sha256, self.config.max_results);
+        for feature in self.feature_names() {
+            key.push('-');
+            key.push_str(&feature.to_lowercase());
+        }
+        key
     }
 
-    let response_json: Value = response.json().map_err(|_err| NotJSON)?;
+    /// Api names of the optional features, sorted and without duplicates
+    fn feature_names(&self) -> Vec<&'static str> {
+        let mut names = self
+            .config
+            .features
+            .iter()
+            .map(|f| f.api_name())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
 
-    let err = &response_json["responses"][0]["error"];
+    /// Annotate `images` with a single request to Google Cloud Vision API
+    ///
+    /// Returns the response of each image, in the same order as `images`.
+    pub(crate) fn annotate_images(
+        &self,
+        images: &[VisionImage],
+    ) -> Result<Vec<Result<Value, CloudVisionError>>, CloudVisionError> {
+        let requests = images
+            .iter()
+            .map(|image| {
+                let image = match image {
+                    VisionImage::Content(content) => {
+                        json!({ "content": base64::encode(&**content) })
+                    }
+                    VisionImage::Uri(uri) => json!({ "source": { "imageUri": uri } }),
+                };
+                let features = std::iter::once("IMAGE_PROPERTIES")
+                    .chain(self.feature_names())
+                    .map(|feature| {
+                        json!({
+                            "maxResults": self.config.max_results,
+                            "type": feature
+                        })
+                    })
+                    .collect::<Vec<Value>>();
+                json!({
+                    "image": image,
+                    "features": features
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        let request = json!({ "requests": requests });
+        let api_key = self.api_key()?;
+
+        let mut response = self
+            .api_http
+            .post_json(&self.config.endpoint, &[("key", &api_key.0)], &request)
+            .map_err(|_err| FailedGCV)?;
+
+        if response.status() != StatusCode::OK {
+            return Err(BadRequest(response.status()));
+        }
+
+        let response_json: Value = response.json().map_err(|_err| NotJSON)?;
+
+        let responses = response_json["responses"].as_array().ok_or(UnknownError)?;
+
+        Ok((0..images.len())
+            .map(|i| match responses.get(i) {
+                Some(image_response) => check_response(image_response),
+                None => Err(UnknownError),
+            })
+            .collect())
+    }
+}
+
+/// Check if the response of an image is an error
+fn check_response(image_response: &Value) -> Result<Value, CloudVisionError> {
+    let err = &image_response["error"];
 
     if err.is_object() {
         if let Some(error_message) = &err["message"].as_str() {
@@ -68,12 +255,12 @@ fn use_cloud_vision_api(image_url: &Url) -> Result<Value, CloudVisionError> {
         };
     }
 
-    Ok(response_json)
+    Ok(image_response.to_owned())
 }
 
-/// Extract `Vec<Color>` with given `Value`
-fn extract_colors(val: &Value) -> Result<Vec<Color>, CloudVisionError> {
-    let colors = &val["responses"][0]["imagePropertiesAnnotation"]["dominantColors"]["colors"];
+/// Extract `Vec<Color>` from the response of an image
+pub(crate) fn extract_colors(image_response: &Value) -> Result<Vec<Color>, CloudVisionError> {
+    let colors = &image_response["imagePropertiesAnnotation"]["dominantColors"]["colors"];
 
     match colors.as_array() {
         Some(color_ary) => {
@@ -84,10 +271,52 @@ fn extract_colors(val: &Value) -> Result<Vec<Color>, CloudVisionError> {
                     color_vec.push(color);
                 };
             });
+
+            if color_vec.is_empty() {
+                return Err(VectorIsEmpty);
+            }
             Ok(color_vec)
         }
 
-        None => Err(UnableToParseColorData(val.to_owned())),
+        None => Err(UnableToParseColorData(image_response.to_owned())),
+    }
+}
+
+/// On-disk cache of the responses of Google Cloud Vision API, keyed by the
+/// SHA-256 of the image and the requested features
+///
+/// Each response is stored as is in `<key>.json`, so cached responses can
+/// also be served by a stand-in server.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    /// Create an new instance of `ResponseCache` which stores responses under `dir`
+    pub(crate) fn new<P: AsRef<Path>>(dir: P) -> ResponseCache {
+        ResponseCache {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Get the cached response of the image with given hash
+    pub(crate) fn get(&self, sha256: &str) -> Option<Value> {
+        let content = fs::read(self.path(sha256)).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    /// Store the response of the image with given hash
+    pub(crate) fn put(&self, sha256: &str, image_response: &Value) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(
+            self.path(sha256),
+            serde_json::to_vec_pretty(image_response)?,
+        )
+    }
+
+    fn path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", sha256))
     }
 }
 
@@ -100,6 +329,9 @@ pub struct Color {
     pub score: f32,
     /// Color in hex string
     pub hex_color: String,
+    /// Color family such as 青/blue
+    #[serde(default)]
+    pub family: Option<ColorName>,
 }
 
 /// Construct `Color` struct with given `Value`
@@ -122,23 +354,27 @@ fn to_color(value: &Value) -> Option<Color> {
         pixel_fraction,
         score,
         hex_color,
+        family: None,
     };
 
     Some(color_struct)
 }
 
-/// Get image content and encod it with base64
-fn get_base64_image(image_url: &Url) -> Option<String> {
-    let mut response = reqwest::get(image_url.to_owned()).ok()?;
+/// Get image content
+pub(crate) fn get_image(http: &HttpClient, image_url: &Url) -> Result<Vec<u8>, CloudVisionError> {
+    let fetch = || -> Option<Vec<u8>> {
+        let mut response = http.get(image_url.to_owned()).ok()?;
 
-    if response.status() != StatusCode::OK {
-        return None;
-    }
+        if response.status() != StatusCode::OK {
+            return None;
+        }
 
-    let mut buf: Vec<u8> = vec![];
-    response.copy_to(&mut buf).ok()?;
-    let encoded = base64::encode(&buf);
-    Some(encoded)
+        let mut buf: Vec<u8> = vec![];
+        response.copy_to(&mut buf).ok()?;
+        Some(buf)
+    };
+
+    fetch().ok_or_else(|| UnableToFetchImage(image_url.to_owned()))
 }
 
 use super::cloud_vision::CloudVisionError::*;
@@ -146,14 +382,14 @@ use super::cloud_vision::CloudVisionError::*;
 #[derive(Debug, PartialEq, Clone)]
 pub enum CloudVisionError {
     UnableToFetchImage(Url),
-    BadRequest(Url),
+    BadRequest(StatusCode),
     FailedToParseImage(String),
     UnableToParseColorData(Value),
     UnknownError,
     VectorIsEmpty,
     FailedGCV,
     NotJSON,
-    KeyFileNotFound(String),
+    MissingApiKey(String),
 }
 
 impl std::error::Error for CloudVisionError {}
@@ -161,7 +397,7 @@ impl std::error::Error for CloudVisionError {}
 impl fmt::Display for CloudVisionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
         let error_message: String = match self {
-            BadRequest(url) => format!("Bad request: {}", url),
+            BadRequest(status) => format!("Bad request: {}", status),
             FailedToParseImage(msg) => format!("Cloud vision api failed to parse image: {}", msg),
             UnableToParseColorData(val) => format!("Unable to parse data: {:#?}", val),
             UnknownError => String::from("Unknown error"),
@@ -169,9 +405,7 @@ impl fmt::Display for CloudVisionError {
             FailedGCV => String::from("Failed to request Google Cloud Vision API"),
             UnableToFetchImage(url) => format!("Unable to fetch image from url: {}", url),
             NotJSON => String::from("Response body is not JSON"),
-            KeyFileNotFound(filepath) => {
-                format!("Cloud vision api key file not found at: {}", filepath)
-            }
+            MissingApiKey(msg) => msg.to_owned(),
         };
         write!(f, "{}", error_message)
     }
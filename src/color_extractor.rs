@@ -0,0 +1,1116 @@
+//! # Color Extractor
+//!
+//! This module exports `ColorExtractor` which is used by
+//! `ButterflyCollector::fetch_dominant_colors` to obtain the dominant colors
+//! of a butterfly. There are two implementations:
+//!
+//! - `CloudVision` sends the image to Google Cloud Vision API
+//! - `LocalColorExtractor` runs k-means over the downloaded image, which works
+//!   offline and without an API key
+//!
+//! Images sit on the table background recorded in `Butterfly::bgcolor`, so
+//! each backend also produces a palette with the background removed. The
+//! near-white margin around the photo is found in the downloaded image when
+//! there is one, and near-white colors are removed otherwise.
+
+use image::{Rgb, RgbImage};
+use log::warn;
+use reqwest::Url;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::butterfly::Butterfly;
+use super::cloud_vision::{
+    extract_colors, get_image, CloudVisionConfig, Color, ImageMode, ResponseCache, VisionClient,
+    VisionImage, MAX_BATCH_SIZE,
+};
+use super::color_space;
+use super::constants::*;
+use super::download::sha256_bytes;
+use super::errors::ButterflyError;
+use super::http_client::HttpClient;
+use super::image_annotations::{extract_annotations, ImageAnnotations};
+
+// Number of colors extracted by `LocalColorExtractor`, same as Cloud Vision
+const DEFAULT_CLUSTERS: usize = 10;
+// Number of k-means iterations
+const DEFAULT_ITERATIONS: usize = 20;
+// Maximum number of pixels used for clustering
+const MAX_SAMPLES: usize = 20_000;
+// Distance in RGB under which a color is considered as the background
+const BACKGROUND_TOLERANCE: f32 = 40.0;
+// Pixels whose channels are all above this value are considered as white
+// margin when they are connected to the border of the image
+const NEAR_WHITE: u8 = 235;
+// Number of images fetched by `CloudVision` kept in memory, enough for the
+// batches sent in parallel
+const MAX_FETCHED_IMAGES: usize = 64;
+
+/// Dominant colors of a butterfly
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Palette {
+    /// Dominant colors of the whole image
+    pub dominant_colors: Vec<Color>,
+    /// Dominant colors with the background removed
+    pub foreground_colors: Vec<Color>,
+    /// Other annotations, when the backend was asked for them
+    pub annotations: Option<ImageAnnotations>,
+}
+
+/// Backend which extracts dominant colors of a butterfly
+pub trait ColorExtractor: Send + Sync + fmt::Debug {
+    /// Name of the backend used in logs
+    fn name(&self) -> &str;
+
+    /// Extract dominant colors of `butterfly`, most dominant first
+    fn extract_colors(&self, butterfly: &Butterfly) -> anyhow::Result<Vec<Color>>;
+
+    /// Extract both raw and background-corrected dominant colors of `butterfly`
+    ///
+    /// By default, the background colors are removed from the raw palette
+    /// and the fractions of remaining colors are scaled back to sum up to 1.
+    fn extract_palette(&self, butterfly: &Butterfly) -> anyhow::Result<Palette> {
+        let dominant_colors = self.extract_colors(butterfly)?;
+        let foreground_colors = BackgroundMask::new(&butterfly.bgcolor).filter(&dominant_colors);
+        Ok(Palette {
+            dominant_colors,
+            foreground_colors,
+            annotations: None,
+        })
+    }
+
+    /// Number of butterflies passed to `extract_palettes` at once
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Extract palettes of `butterflies`, in the same order
+    ///
+    /// Backends which can process several images at once should override this.
+    fn extract_palettes(&self, butterflies: &[Butterfly]) -> Vec<anyhow::Result<Palette>> {
+        butterflies
+            .iter()
+            .map(|butterfly| self.extract_palette(butterfly))
+            .collect()
+    }
+}
+
+/// Decides which colors belong to the background of an image
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BackgroundMask {
+    bgcolor: Option<[f32; 3]>,
+}
+
+impl BackgroundMask {
+    /// Create an new instance of `BackgroundMask` for the table background
+    /// `bgcolor`, given as 6 digit hex such as `#ccffff`
+    pub(crate) fn new(bgcolor: &str) -> BackgroundMask {
+        BackgroundMask {
+            bgcolor: parse_hex(bgcolor),
+        }
+    }
+
+    /// Check if `pixel` is close to the table background
+    pub(crate) fn is_background(&self, pixel: &[f32; 3]) -> bool {
+        self.bgcolor
+            .map(|bg| distance(&bg, pixel) < BACKGROUND_TOLERANCE.powi(2))
+            .unwrap_or(false)
+    }
+
+    /// Pixels of `image` which are neither close to the table background nor
+    /// part of the near-white margin around the photo
+    ///
+    /// The margin is found by flood filling from the border of the image, so
+    /// near-white parts of the butterfly itself, such as white wings, are kept.
+    pub(crate) fn foreground(&self, image: &RgbImage) -> Vec<[f32; 3]> {
+        let margin = self.white_margin(image);
+        image
+            .pixels()
+            .zip(margin)
+            .filter(|(_, is_margin)| !is_margin)
+            .map(|(pixel, _)| to_rgb(pixel))
+            .filter(|pixel| !self.is_background(pixel))
+            .collect()
+    }
+
+    /// Flags near-white pixels connected to the border of `image`, in the
+    /// order of `RgbImage::pixels`
+    ///
+    /// Background pixels are crossed as well, since the margin may be
+    /// separated from the border by the table background.
+    fn white_margin(&self, image: &RgbImage) -> Vec<bool> {
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Vec::new();
+        }
+        let index = |x: u32, y: u32| (y * width + x) as usize;
+        let passable = |x: u32, y: u32| {
+            let pixel = image.get_pixel(x, y);
+            is_near_white(pixel) || self.is_background(&to_rgb(pixel))
+        };
+
+        let mut visited = vec![false; (width * height) as usize];
+        let mut queue = VecDeque::new();
+        for x in 0..width {
+            queue.push_back((x, 0));
+            queue.push_back((x, height - 1));
+        }
+        for y in 0..height {
+            queue.push_back((0, y));
+            queue.push_back((width - 1, y));
+        }
+
+        while let Some((x, y)) = queue.pop_front() {
+            if visited[index(x, y)] || !passable(x, y) {
+                continue;
+            }
+            visited[index(x, y)] = true;
+            if x > 0 {
+                queue.push_back((x - 1, y));
+            }
+            if x + 1 < width {
+                queue.push_back((x + 1, y));
+            }
+            if y > 0 {
+                queue.push_back((x, y - 1));
+            }
+            if y + 1 < height {
+                queue.push_back((x, y + 1));
+            }
+        }
+
+        image
+            .pixels()
+            .zip(visited)
+            .map(|(pixel, visited)| visited && is_near_white(pixel))
+            .collect()
+    }
+
+    /// Remove background and near-white colors from `colors`, scaling the
+    /// fraction of the remaining ones so they sum up to 1
+    ///
+    /// Near-white colors cannot be told apart from white wings without the
+    /// image, so they are all removed.
+    pub(crate) fn filter(&self, colors: &[Color]) -> Vec<Color> {
+        let foreground = colors
+            .iter()
+            .filter(|color| match parse_hex(&color.hex_color) {
+                Some(rgb) => {
+                    !self.is_background(&rgb) && !rgb.iter().all(|c| *c >= f32::from(NEAR_WHITE))
+                }
+                None => true,
+            })
+            .cloned()
+            .collect::<Vec<Color>>();
+
+        let total: f32 = foreground.iter().map(|c| c.pixel_fraction).sum();
+        if total <= 0.0 {
+            return foreground;
+        }
+        foreground
+            .into_iter()
+            .map(|color| Color {
+                pixel_fraction: color.pixel_fraction / total,
+                ..color
+            })
+            .collect()
+    }
+
+    /// Weigh `colors` by the foreground pixels of `image`
+    ///
+    /// Each pixel of `foreground` counts for the closest of `colors` which is
+    /// not the table background, so colors found only in the background or
+    /// the white margin are removed.
+    pub(crate) fn weigh(&self, colors: &[Color], image: &RgbImage) -> Vec<Color> {
+        let colors = colors
+            .iter()
+            .filter_map(|color| parse_hex(&color.hex_color).map(|rgb| (color, rgb)))
+            .filter(|(_, rgb)| !self.is_background(rgb))
+            .collect::<Vec<_>>();
+        let centroids = colors.iter().map(|(_, rgb)| *rgb).collect::<Vec<_>>();
+        let pixels = self.foreground(image);
+        if centroids.is_empty() || pixels.is_empty() {
+            return Vec::new();
+        }
+
+        let mut counts = vec![0; centroids.len()];
+        for pixel in pixels.iter() {
+            counts[nearest(&centroids, pixel)] += 1;
+        }
+
+        let total = pixels.len() as f32;
+        colors
+            .into_iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|((color, _), count)| Color {
+                pixel_fraction: count as f32 / total,
+                ..color.clone()
+            })
+            .collect()
+    }
+}
+
+/// Extract colors with Google Cloud Vision API
+///
+/// Images are sent in batches, and responses are cached under
+/// `CLOUD_VISION_CACHE_DIRECTORY` so images which were already analyzed are
+/// not sent again. Images downloaded by `fetch_images` are read from the disk,
+/// and other images are fetched once while they are needed.
+#[derive(Debug, Clone)]
+pub struct CloudVision {
+    vision: VisionClient,
+    cache: Option<ResponseCache>,
+    fetched: Arc<FetchedImages>,
+}
+
+// Image of an url, locked while it is being fetched
+type ImageSlot = Arc<Mutex<Option<Arc<Vec<u8>>>>>;
+
+/// Images fetched from the website, keyed by url
+///
+/// Only the latest `capacity` images are kept, older ones are fetched again
+/// if they are needed.
+#[derive(Debug)]
+struct FetchedImages {
+    capacity: usize,
+    state: Mutex<FetchedState>,
+}
+
+#[derive(Debug, Default)]
+struct FetchedState {
+    slots: HashMap<String, ImageSlot>,
+    // Urls of fetched images, oldest first
+    order: VecDeque<String>,
+}
+
+impl FetchedImages {
+    fn new(capacity: usize) -> FetchedImages {
+        FetchedImages {
+            capacity,
+            state: Mutex::new(FetchedState::default()),
+        }
+    }
+
+    /// Get the image of `url`, calling `fetch` unless it has been fetched
+    ///
+    /// Threads asking for the same url wait for the one fetching it. Urls
+    /// which fail to be fetched are forgotten.
+    fn get_or_fetch<F>(&self, url: &str, fetch: F) -> anyhow::Result<Arc<Vec<u8>>>
+    where
+        F: FnOnce() -> anyhow::Result<Vec<u8>>,
+    {
+        let slot = self
+            .state
+            .lock()
+            .unwrap()
+            .slots
+            .entry(url.to_string())
+            .or_default()
+            .clone();
+
+        let mut image = slot.lock().unwrap();
+        if let Some(image) = image.as_ref() {
+            return Ok(image.clone());
+        }
+        let fetched = match fetch() {
+            Ok(fetched) => Arc::new(fetched),
+            Err(err) => {
+                drop(image);
+                let mut state = self.state.lock().unwrap();
+                // Another thread may have replaced the slot in the meantime
+                if state.slots.get(url).is_some_and(|s| Arc::ptr_eq(s, &slot)) {
+                    state.slots.remove(url);
+                }
+                return Err(err);
+            }
+        };
+        *image = Some(fetched.clone());
+        drop(image);
+
+        let mut state = self.state.lock().unwrap();
+        state.order.push_back(url.to_string());
+        while state.order.len() > self.capacity {
+            if let Some(oldest) = state.order.pop_front() {
+                state.slots.remove(&oldest);
+            }
+        }
+        Ok(fetched)
+    }
+}
+
+impl CloudVision {
+    /// Create an new instance of `CloudVision`
+    ///
+    /// The api key is read from the source given in `config` when the first
+    /// request is sent, so cached responses can be replayed without it.
+    ///
+    /// ```rust
+    /// let extractor = CloudVision::new(
+    ///     HttpClient::default(),
+    ///     CloudVisionConfig {
+    ///         api_key: ApiKeySource::Env("VISION_API_KEY".to_string()),
+    ///         ..CloudVisionConfig::default()
+    ///     },
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn new(http: HttpClient, config: CloudVisionConfig) -> Result<CloudVision, ButterflyError> {
+        Ok(CloudVision {
+            vision: VisionClient::new(http, config)?,
+            cache: Some(ResponseCache::new(CLOUD_VISION_CACHE_DIRECTORY)),
+            fetched: Arc::new(FetchedImages::new(MAX_FETCHED_IMAGES)),
+        })
+    }
+
+    /// Cache responses under `dir`
+    pub fn with_cache_dir<P: AsRef<Path>>(mut self, dir: P) -> CloudVision {
+        self.cache = Some(ResponseCache::new(dir));
+        self
+    }
+
+    /// Send every image to the API, without reading or writing the cache
+    pub fn without_cache(mut self) -> CloudVision {
+        self.cache = None;
+        self
+    }
+
+    fn cached(&self, sha256: &str) -> Option<Value> {
+        let key = self.vision.cache_key(sha256);
+        self.cache.as_ref().and_then(|cache| cache.get(&key))
+    }
+
+    /// Get the response of each butterfly, from the cache when possible
+    fn annotate(&self, butterflies: &[Butterfly]) -> Vec<anyhow::Result<Value>> {
+        let mut results = butterflies.iter().map(|_| None).collect::<Vec<_>>();
+        let mut pending = Vec::new();
+        let mut images = Vec::new();
+
+        for (i, butterfly) in butterflies.iter().enumerate() {
+            // Known hash of the downloaded image avoids fetching it at all
+            if let Some(response) = butterfly.img_sha256.as_ref().and_then(|s| self.cached(s)) {
+                results[i] = Some(Ok(response));
+                continue;
+            }
+
+            if self.vision.image_mode() == ImageMode::Uri {
+                match image_url(butterfly) {
+                    Ok(url) => {
+                        pending.push((i, butterfly.img_sha256.to_owned()));
+                        images.push(VisionImage::Uri(url.to_string()));
+                    }
+                    Err(err) => results[i] = Some(Err(err)),
+                }
+                continue;
+            }
+
+            let image = match self.read_image(butterfly) {
+                Ok(image) => image,
+                Err(err) => {
+                    results[i] = Some(Err(err));
+                    continue;
+                }
+            };
+            let sha256 = sha256_bytes(&image);
+            match self.cached(&sha256) {
+                Some(response) => results[i] = Some(Ok(response)),
+                None => {
+                    pending.push((i, Some(sha256)));
+                    images.push(VisionImage::Content(image));
+                }
+            }
+        }
+
+        if !images.is_empty() {
+            match self.vision.annotate_images(&images) {
+                Ok(responses) => {
+                    for ((i, sha256), response) in pending.into_iter().zip(responses) {
+                        if let (Ok(response), Some(cache), Some(sha256)) =
+                            (&response, &self.cache, sha256)
+                        {
+                            let key = self.vision.cache_key(&sha256);
+                            if let Err(err) = cache.put(&key, response) {
+                                warn!("Unable to cache response of {}: {}", sha256, err);
+                            }
+                        }
+                        results[i] = Some(response.map_err(anyhow::Error::from));
+                    }
+                }
+                Err(err) => {
+                    for (i, _sha256) in pending.into_iter() {
+                        results[i] = Some(Err(err.clone().into()));
+                    }
+                }
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("Every butterfly has a response"))
+            .collect()
+    }
+
+    /// Read the image downloaded on `img_path`, or fetch it if it is not
+    /// on the disk and has not been fetched yet
+    fn read_image(&self, butterfly: &Butterfly) -> anyhow::Result<Arc<Vec<u8>>> {
+        if let Some(img_path) = &butterfly.img_path {
+            if let Ok(content) = fs::read(img_path) {
+                return Ok(Arc::new(content));
+            }
+        }
+
+        let img_url = image_url(butterfly)?;
+        self.fetched.get_or_fetch(img_url.as_str(), || {
+            Ok(get_image(&self.vision.http, &img_url)?)
+        })
+    }
+}
+
+/// Url of the image of `butterfly`
+fn image_url(butterfly: &Butterfly) -> anyhow::Result<Url> {
+    Ok(Url::parse(BUTTERFLY_URL)?.join(&butterfly.img_src)?)
+}
+
+impl ColorExtractor for CloudVision {
+    fn name(&self) -> &str {
+        "Google Cloud Vision"
+    }
+
+    fn extract_colors(&self, butterfly: &Butterfly) -> anyhow::Result<Vec<Color>> {
+        let response = self.annotate(std::slice::from_ref(butterfly)).remove(0)?;
+        Ok(extract_colors(&response)?)
+    }
+
+    fn batch_size(&self) -> usize {
+        MAX_BATCH_SIZE
+    }
+
+    fn extract_palettes(&self, butterflies: &[Butterfly]) -> Vec<anyhow::Result<Palette>> {
+        self.annotate(butterflies)
+            .into_iter()
+            .zip(butterflies.iter())
+            .map(|(response, butterfly)| {
+                let response = response?;
+                let dominant_colors = extract_colors(&response)?;
+                let foreground_colors = foreground_colors(butterfly, &dominant_colors);
+                let annotations = if self.vision.features().is_empty() {
+                    None
+                } else {
+                    Some(extract_annotations(&response))
+                };
+                Ok(Palette {
+                    dominant_colors,
+                    foreground_colors,
+                    annotations,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Dominant colors of `butterfly` without the background, found in the
+/// downloaded image when there is one
+fn foreground_colors(butterfly: &Butterfly, dominant_colors: &[Color]) -> Vec<Color> {
+    let mask = BackgroundMask::new(&butterfly.bgcolor);
+    if butterfly.img_path.is_none() {
+        return mask.filter(dominant_colors);
+    }
+    match read_image(butterfly) {
+        Ok(image) => mask.weigh(dominant_colors, &image),
+        Err(err) => {
+            warn!("{}, near-white colors are removed instead", err);
+            mask.filter(dominant_colors)
+        }
+    }
+}
+
+/// Extract colors from the downloaded image with k-means clustering
+///
+/// Images have to be downloaded with `fetch_images` beforehand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalColorExtractor {
+    /// Number of colors to extract
+    pub clusters: usize,
+    /// Number of k-means iterations
+    pub iterations: usize,
+}
+
+impl Default for LocalColorExtractor {
+    fn default() -> Self {
+        LocalColorExtractor {
+            clusters: DEFAULT_CLUSTERS,
+            iterations: DEFAULT_ITERATIONS,
+        }
+    }
+}
+
+impl ColorExtractor for LocalColorExtractor {
+    fn name(&self) -> &str {
+        "local k-means"
+    }
+
+    fn extract_colors(&self, butterfly: &Butterfly) -> anyhow::Result<Vec<Color>> {
+        let image = read_image(butterfly)?;
+        Ok(self.cluster(&image.pixels().map(to_rgb).collect::<Vec<_>>()))
+    }
+
+    /// Background pixels are masked out before clustering the foreground
+    fn extract_palette(&self, butterfly: &Butterfly) -> anyhow::Result<Palette> {
+        let image = read_image(butterfly)?;
+        let pixels = image.pixels().map(to_rgb).collect::<Vec<_>>();
+        let foreground = BackgroundMask::new(&butterfly.bgcolor).foreground(&image);
+
+        Ok(Palette {
+            dominant_colors: self.cluster(&pixels),
+            foreground_colors: self.cluster(&foreground),
+            annotations: None,
+        })
+    }
+}
+
+/// Read the downloaded image of `butterfly`
+fn read_image(butterfly: &Butterfly) -> anyhow::Result<RgbImage> {
+    let img_path = butterfly
+        .img_path
+        .as_ref()
+        .ok_or_else(|| ButterflyError::ImageNotDownloaded(butterfly.jp_name.to_owned()))?;
+
+    Ok(image::open(img_path)
+        .map_err(|e| ButterflyError::FailedToReadImage(img_path.to_owned(), e.to_string()))?
+        .to_rgb())
+}
+
+fn to_rgb(pixel: &Rgb<u8>) -> [f32; 3] {
+    [
+        f32::from(pixel[0]),
+        f32::from(pixel[1]),
+        f32::from(pixel[2]),
+    ]
+}
+
+fn is_near_white(pixel: &Rgb<u8>) -> bool {
+    pixel.0.iter().all(|c| *c >= NEAR_WHITE)
+}
+
+impl LocalColorExtractor {
+    /// Cluster `pixels` and return a `Color` for each non empty cluster
+    fn cluster(&self, pixels: &[[f32; 3]]) -> Vec<Color> {
+        if pixels.is_empty() || self.clusters == 0 {
+            return Vec::new();
+        }
+
+        let step = (pixels.len() / MAX_SAMPLES).max(1);
+        let samples = pixels.iter().step_by(step).cloned().collect::<Vec<_>>();
+
+        // Start from samples spread evenly over the brightness range so the
+        // result is deterministic
+        let mut by_brightness = samples.clone();
+        by_brightness.sort_by(|a, b| brightness(a).partial_cmp(&brightness(b)).unwrap());
+        let k = self.clusters.min(samples.len());
+        let mut centroids = (0..k)
+            .map(|i| by_brightness[i * by_brightness.len() / k])
+            .collect::<Vec<_>>();
+
+        let mut counts = vec![0; k];
+        for _ in 0..self.iterations.max(1) {
+            let mut sums = vec![[0.0f32; 3]; k];
+            counts = vec![0; k];
+
+            for sample in samples.iter() {
+                let nearest = nearest(&centroids, sample);
+                counts[nearest] += 1;
+                for c in 0..3 {
+                    sums[nearest][c] += sample[c];
+                }
+            }
+
+            let mut moved = false;
+            for (i, centroid) in centroids.iter_mut().enumerate() {
+                // Empty clusters keep their centroid
+                if counts[i] == 0 {
+                    continue;
+                }
+                let mean = [
+                    sums[i][0] / counts[i] as f32,
+                    sums[i][1] / counts[i] as f32,
+                    sums[i][2] / counts[i] as f32,
+                ];
+                if distance(centroid, &mean) > 0.25 {
+                    moved = true;
+                }
+                *centroid = mean;
+            }
+
+            if !moved {
+                break;
+            }
+        }
+
+        let total = samples.len() as f32;
+        let mut colors = centroids
+            .iter()
+            .zip(counts.iter())
+            .filter(|(_, count)| **count > 0)
+            .map(|(centroid, count)| {
+                let fraction = *count as f32 / total;
+                Color {
+                    pixel_fraction: fraction,
+                    score: fraction,
+                    hex_color: to_hex(centroid),
+                    family: None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        colors.sort_by(|a, b| b.pixel_fraction.partial_cmp(&a.pixel_fraction).unwrap());
+        colors
+    }
+}
+
+/// Index of the centroid closest to `pixel`
+fn nearest(centroids: &[[f32; 3]], pixel: &[f32; 3]) -> usize {
+    let mut best = 0;
+    let mut best_distance = f32::MAX;
+    for (i, centroid) in centroids.iter().enumerate() {
+        let d = distance(centroid, pixel);
+        if d < best_distance {
+            best = i;
+            best_distance = d;
+        }
+    }
+    best
+}
+
+/// Squared euclidean distance in RGB
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum()
+}
+
+fn brightness(pixel: &[f32; 3]) -> f32 {
+    0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2]
+}
+
+/// Parse 6 digit hex string such as `#ffcc00` into RGB
+fn parse_hex(hex_color: &str) -> Option<[f32; 3]> {
+    color_space::parse_hex(hex_color).map(|rgb| rgb.map(f32::from))
+}
+
+/// Convert RGB into 6 digit hex string such as `#ffcc00`
+fn to_hex(pixel: &[f32; 3]) -> String {
+    let rgb = pixel
+        .iter()
+        .map(|c| c.round().clamp(0.0, 255.0) as u8)
+        .collect::<Vec<u8>>();
+    format!("#{}", hex::encode(rgb))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud_vision::ApiKeySource;
+    use crate::http_client::tests::serve;
+    use crate::image_annotations::VisionFeature;
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn cloud_vision_uses_configured_endpoint_and_key() {
+        let (url, handle) = serve(3, |request_line| {
+            if request_line.starts_with("GET /robots.txt") {
+                (404, Vec::new())
+            } else if request_line.starts_with("GET /a.jpg") {
+                (200, b"image".to_vec())
+            } else {
+                let response = format!(r#"{{"responses": [{}]}}"#, RED_RESPONSE);
+                (200, response.into_bytes())
+            }
+        });
+
+        let config = CloudVisionConfig {
+            max_results: 3,
+            ..mock_config(&url, ImageMode::Content)
+        };
+        let extractor = CloudVision::new(HttpClient::default(), config)
+            .unwrap()
+            .without_cache();
+        let butterfly = Butterfly::new("", &format!("{}/a.jpg", url), "", "#ffffff", "", "", "");
+
+        let colors = extractor.extract_colors(&butterfly).unwrap();
+        let requests = handle.join().unwrap();
+
+        assert_eq!(colors[0].hex_color, "#ff0000");
+        assert!(requests[2].starts_with("POST /v1/images:annotate?key=mock-key "));
+        assert!(requests[2].contains(r#""maxResults":3"#));
+    }
+
+    const RED_RESPONSE: &str = r#"{"imagePropertiesAnnotation": {"dominantColors": {"colors": [
+        {"color": {"red": 255, "green": 0, "blue": 0}, "score": 0.9, "pixelFraction": 0.8}
+    ]}}}"#;
+
+    fn mock_config(url: &str, image_mode: ImageMode) -> CloudVisionConfig {
+        env::set_var("BUTTERFLY_EXTRACTOR_MOCK_KEY", "mock-key");
+        CloudVisionConfig {
+            endpoint: format!("{}/v1/images:annotate", url),
+            api_key: ApiKeySource::Env("BUTTERFLY_EXTRACTOR_MOCK_KEY".to_string()),
+            image_mode,
+            ..CloudVisionConfig::default()
+        }
+    }
+
+    #[test]
+    fn images_are_read_from_disk_or_fetched_once() {
+        let (url, handle) = serve(3, |request_line| {
+            if request_line.starts_with("GET /robots.txt") {
+                (404, Vec::new())
+            } else if request_line.starts_with("GET /a.jpg") {
+                (200, b"remote image".to_vec())
+            } else {
+                let response = format!(r#"{{"responses": [{0}, {0}, {0}]}}"#, RED_RESPONSE);
+                (200, response.into_bytes())
+            }
+        });
+
+        let img_path = env::temp_dir().join("butterfly_extractor_local_image.jpg");
+        fs::write(&img_path, b"local image").unwrap();
+        let remote = Butterfly::new("", &format!("{}/a.jpg", url), "", "#ffffff", "", "", "");
+        let mut local = remote.clone();
+        local.img_path = Some(img_path.to_str().unwrap().to_string());
+
+        let extractor =
+            CloudVision::new(HttpClient::default(), mock_config(&url, ImageMode::Content))
+                .unwrap()
+                .without_cache();
+        let palettes = extractor.extract_palettes(&[remote.clone(), remote, local]);
+        let requests = handle.join().unwrap();
+
+        assert!(palettes.iter().all(|palette| palette.is_ok()));
+        assert!(requests[2].contains(&base64::encode(b"remote image")));
+        assert!(requests[2].contains(&base64::encode(b"local image")));
+        fs::remove_file(&img_path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_fetches_of_an_image_are_merged() {
+        let fetched = FetchedImages::new(MAX_FETCHED_IMAGES);
+        let fetches = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let image = fetched
+                        .get_or_fetch("a.jpg", || {
+                            fetches.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(Duration::from_millis(20));
+                            Ok(b"image".to_vec())
+                        })
+                        .unwrap();
+                    assert_eq!(image.as_slice(), b"image");
+                });
+            }
+        });
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn oldest_fetched_images_are_dropped() {
+        let fetched = FetchedImages::new(2);
+        for url in ["a.jpg", "b.jpg", "c.jpg"].iter() {
+            fetched
+                .get_or_fetch(url, || Ok(url.as_bytes().to_vec()))
+                .unwrap();
+        }
+
+        let refetched = AtomicUsize::new(0);
+        let fetch = || {
+            refetched.fetch_add(1, Ordering::SeqCst);
+            Ok(Vec::new())
+        };
+        fetched.get_or_fetch("c.jpg", fetch).unwrap();
+        fetched.get_or_fetch("a.jpg", fetch).unwrap();
+
+        assert_eq!(refetched.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn failed_fetches_leave_no_slot() {
+        let fetched = FetchedImages::new(2);
+        for _ in 0..3 {
+            assert!(fetched
+                .get_or_fetch("a.jpg", || Err(anyhow::anyhow!("not found")))
+                .is_err());
+        }
+
+        let state = fetched.state.lock().unwrap();
+        assert!(state.slots.is_empty());
+        assert!(state.order.is_empty());
+    }
+
+    #[test]
+    fn image_uri_mode_sends_urls() {
+        let (url, handle) = serve(1, |_request_line| {
+            let response = format!(r#"{{"responses": [{}]}}"#, RED_RESPONSE);
+            (200, response.into_bytes())
+        });
+
+        let butterfly = Butterfly::new("", "butterfly_img/a.jpg", "", "#ffffff", "", "", "");
+        let extractor = CloudVision::new(HttpClient::default(), mock_config(&url, ImageMode::Uri))
+            .unwrap()
+            .without_cache();
+        let colors = extractor.extract_colors(&butterfly).unwrap();
+        let requests = handle.join().unwrap();
+
+        assert_eq!(colors[0].hex_color, "#ff0000");
+        assert!(requests[0]
+            .contains(r#""imageUri":"http://biokite.com/worldbutterfly/butterfly_img/a.jpg""#));
+    }
+
+    #[test]
+    fn missing_api_key_is_reported() {
+        let config = CloudVisionConfig {
+            api_key: ApiKeySource::Env("BUTTERFLY_EXTRACTOR_MISSING_KEY".to_string()),
+            image_mode: ImageMode::Uri,
+            ..CloudVisionConfig::default()
+        };
+        let butterfly = Butterfly::new("", "butterfly_img/a.jpg", "", "#ffffff", "", "", "");
+
+        let extractor = CloudVision::new(HttpClient::default(), config)
+            .unwrap()
+            .without_cache();
+        let err = extractor.extract_colors(&butterfly).unwrap_err();
+
+        assert!(err.to_string().contains("BUTTERFLY_EXTRACTOR_MISSING_KEY"));
+    }
+
+    #[test]
+    fn local_extractor_finds_dominant_colors() {
+        let img_path = env::temp_dir().join("butterfly_extractor_local_colors.png");
+        let image = RgbImage::from_fn(4, 4, |x, _y| {
+            if x == 0 {
+                Rgb([0, 0, 255])
+            } else {
+                Rgb([255, 0, 0])
+            }
+        });
+        image.save(&img_path).unwrap();
+
+        let mut butterfly = Butterfly::new("", "", "", "#ffffff", "", "", "");
+        butterfly.img_path = Some(img_path.to_str().unwrap().to_string());
+
+        let extractor = LocalColorExtractor {
+            clusters: 2,
+            ..LocalColorExtractor::default()
+        };
+        let colors = extractor.extract_colors(&butterfly).unwrap();
+
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0].hex_color, "#ff0000");
+        assert_eq!(colors[0].pixel_fraction, 0.75);
+        assert_eq!(colors[1].hex_color, "#0000ff");
+    }
+
+    #[test]
+    fn local_extractor_requires_downloaded_image() {
+        let butterfly = Butterfly::new("", "", "", "#ffffff", "", "", "");
+
+        assert!(LocalColorExtractor::default()
+            .extract_colors(&butterfly)
+            .is_err());
+    }
+
+    #[test]
+    fn cache_key_depends_on_features_and_max_results() {
+        let key = |features: Vec<VisionFeature>, max_results| {
+            let config = CloudVisionConfig {
+                features,
+                max_results,
+                ..CloudVisionConfig::default()
+            };
+            VisionClient::new(HttpClient::default(), config)
+                .unwrap()
+                .cache_key("abc")
+        };
+        let labels_and_crops = key(
+            vec![
+                VisionFeature::LabelDetection,
+                VisionFeature::CropHints,
+                VisionFeature::LabelDetection,
+            ],
+            10,
+        );
+
+        assert_eq!(key(Vec::new(), 10), "abc-max10");
+        assert_eq!(labels_and_crops, "abc-max10-crop_hints-label_detection");
+        assert_eq!(
+            labels_and_crops,
+            key(
+                vec![VisionFeature::CropHints, VisionFeature::LabelDetection],
+                10
+            )
+        );
+        assert_ne!(key(Vec::new(), 10), key(Vec::new(), 5));
+    }
+
+    #[test]
+    fn cached_responses_are_used_without_requests() {
+        let cache_dir = env::temp_dir().join("butterfly_extractor_cloud_vision_cache");
+        let response = serde_json::json!({
+            "imagePropertiesAnnotation": {
+                "dominantColors": {
+                    "colors": [
+                        {"color": {"red": 204, "green": 255, "blue": 255}, "score": 0.6, "pixelFraction": 0.7},
+                        {"color": {"red": 128, "green": 64, "blue": 32}, "score": 0.3, "pixelFraction": 0.2}
+                    ]
+                }
+            }
+        });
+        ResponseCache::new(&cache_dir)
+            .put("abc-max10", &response)
+            .unwrap();
+
+        // Image url is unreachable, so only the cached response can succeed
+        let mut butterfly =
+            Butterfly::new("", "http://127.0.0.1:9/a.jpg", "", "#ccffff", "", "", "");
+        butterfly.img_sha256 = Some("abc".to_string());
+
+        // No api key is needed to replay cached responses
+        let config = CloudVisionConfig {
+            api_key: ApiKeySource::Env("BUTTERFLY_EXTRACTOR_MISSING_KEY".to_string()),
+            ..CloudVisionConfig::default()
+        };
+        let extractor = CloudVision::new(HttpClient::default(), config)
+            .unwrap()
+            .with_cache_dir(&cache_dir);
+        let palettes = extractor.extract_palettes(&[butterfly]);
+        let palette = palettes[0].as_ref().unwrap();
+
+        assert_eq!(palette.dominant_colors.len(), 2);
+        assert_eq!(palette.foreground_colors[0].hex_color, "#804020");
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[test]
+    fn background_colors_are_removed_from_palette() {
+        let color = |hex: &str, pixel_fraction| Color {
+            pixel_fraction,
+            score: pixel_fraction,
+            hex_color: hex.to_string(),
+            family: None,
+        };
+        let colors = vec![
+            color("#ccffff", 0.5),
+            color("#cafdfb", 0.1),
+            color("#fafafa", 0.2),
+            color("#804020", 0.15),
+            color("#202020", 0.05),
+        ];
+
+        let foreground = BackgroundMask::new("#ccffff").filter(&colors);
+
+        assert_eq!(foreground.len(), 2);
+        assert_eq!(foreground[0].hex_color, "#804020");
+        assert!((foreground[0].pixel_fraction - 0.75).abs() < 1e-6);
+        assert!((foreground[1].pixel_fraction - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn local_extractor_masks_background_pixels() {
+        let img_path = env::temp_dir().join("butterfly_extractor_local_palette.png");
+        let image = RgbImage::from_fn(4, 4, |x, y| match (x, y) {
+            (0, 0) => Rgb([0, 0, 255]),
+            (0, _) => Rgb([255, 0, 0]),
+            _ => Rgb([204, 255, 255]),
+        });
+        image.save(&img_path).unwrap();
+
+        let mut butterfly = Butterfly::new("", "", "", "#ccffff", "", "", "");
+        butterfly.img_path = Some(img_path.to_str().unwrap().to_string());
+
+        let extractor = LocalColorExtractor {
+            clusters: 2,
+            ..LocalColorExtractor::default()
+        };
+        let palette = extractor.extract_palette(&butterfly).unwrap();
+
+        assert_eq!(palette.dominant_colors[0].hex_color, "#ccffff");
+        assert_eq!(palette.foreground_colors[0].hex_color, "#ff0000");
+        assert_eq!(palette.foreground_colors[0].pixel_fraction, 0.75);
+        assert_eq!(palette.foreground_colors[1].hex_color, "#0000ff");
+    }
+
+    #[test]
+    fn only_white_margin_connected_to_border_is_masked() {
+        // White margin and table background around a red butterfly with a
+        // white spot in the middle
+        let image = RgbImage::from_fn(7, 7, |x, y| match (x, y) {
+            (3, 3) => Rgb([250, 250, 250]),
+            (2..=4, 2..=4) => Rgb([255, 0, 0]),
+            (1..=5, 1..=5) => Rgb([204, 255, 255]),
+            _ => Rgb([255, 255, 255]),
+        });
+
+        let foreground = BackgroundMask::new("#ccffff").foreground(&image);
+
+        assert_eq!(foreground.len(), 9);
+        assert_eq!(
+            foreground
+                .iter()
+                .filter(|pixel| **pixel == [250.0, 250.0, 250.0])
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn cloud_vision_palette_is_masked_with_downloaded_image() {
+        let cache_dir = env::temp_dir().join("butterfly_extractor_cloud_vision_margin");
+        let response = serde_json::json!({
+            "imagePropertiesAnnotation": {
+                "dominantColors": {
+                    "colors": [
+                        {"color": {"red": 255, "green": 255, "blue": 255}, "score": 0.5, "pixelFraction": 0.5},
+                        {"color": {"red": 204, "green": 255, "blue": 255}, "score": 0.3, "pixelFraction": 0.3},
+                        {"color": {"red": 255, "green": 0, "blue": 0}, "score": 0.2, "pixelFraction": 0.2}
+                    ]
+                }
+            }
+        });
+        ResponseCache::new(&cache_dir)
+            .put("margin-max10", &response)
+            .unwrap();
+
+        let img_path = env::temp_dir().join("butterfly_extractor_cloud_vision_margin.png");
+        let image = RgbImage::from_fn(7, 7, |x, y| match (x, y) {
+            (3, 3) => Rgb([250, 250, 250]),
+            (2..=4, 2..=4) => Rgb([255, 0, 0]),
+            (1..=5, 1..=5) => Rgb([204, 255, 255]),
+            _ => Rgb([255, 255, 255]),
+        });
+        image.save(&img_path).unwrap();
+
+        let mut butterfly = Butterfly::new("", "", "", "#ccffff", "", "", "");
+        butterfly.img_sha256 = Some("margin".to_string());
+        butterfly.img_path = Some(img_path.to_str().unwrap().to_string());
+
+        let extractor = CloudVision::new(HttpClient::default(), CloudVisionConfig::default())
+            .unwrap()
+            .with_cache_dir(&cache_dir);
+        let palettes = extractor.extract_palettes(&[butterfly]);
+        let palette = palettes[0].as_ref().unwrap();
+
+        // White spot on the wings is kept, while the margin is removed
+        assert_eq!(palette.foreground_colors.len(), 2);
+        assert_eq!(palette.foreground_colors[0].hex_color, "#ffffff");
+        assert!((palette.foreground_colors[0].pixel_fraction - 1.0 / 9.0).abs() < 1e-6);
+        assert_eq!(palette.foreground_colors[1].hex_color, "#ff0000");
+        assert!((palette.foreground_colors[1].pixel_fraction - 8.0 / 9.0).abs() < 1e-6);
+        fs::remove_dir_all(&cache_dir).unwrap();
+        fs::remove_file(&img_path).unwrap();
+    }
+}
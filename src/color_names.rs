@@ -0,0 +1,171 @@
+//! # Color Names
+//!
+//! This module exports `ColorNames` which maps colors to human color families
+//! such as 黒/black or 青/blue. Each family is given by one or more reference
+//! colors, and a color belongs to the family of the closest reference color in
+//! CIELAB space. The families can be loaded from a TOML file.
+//!
+//! ```toml
+//! [[families]]
+//! jp_name = "青"
+//! eng_name = "blue"
+//! colors = ["#1e50c8", "#0000ff", "#4682b4"]
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use super::cloud_vision::Color;
+use super::color_space::Lab;
+use super::errors::ButterflyError::{self, *};
+
+/// Name of a color family
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ColorName {
+    /// Japanese name such as `青`
+    pub jp_name: String,
+    /// English name such as `blue`
+    pub eng_name: String,
+}
+
+impl ColorName {
+    /// Check if `name` is either the Japanese or the English name
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.jp_name == name || self.eng_name.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Color family and its reference colors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColorFamily {
+    /// Japanese name such as `青`
+    pub jp_name: String,
+    /// English name such as `blue`
+    pub eng_name: String,
+    /// Reference colors in 6 digit hex
+    pub colors: Vec<String>,
+}
+
+impl ColorFamily {
+    fn new(jp_name: &str, eng_name: &str, colors: &[&str]) -> ColorFamily {
+        ColorFamily {
+            jp_name: jp_name.to_string(),
+            eng_name: eng_name.to_string(),
+            colors: colors.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// Name of this family
+    pub fn name(&self) -> ColorName {
+        ColorName {
+            jp_name: self.jp_name.to_owned(),
+            eng_name: self.eng_name.to_owned(),
+        }
+    }
+}
+
+/// Palette of color families used to name colors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColorNames {
+    /// List of color families
+    pub families: Vec<ColorFamily>,
+}
+
+impl Default for ColorNames {
+    fn default() -> Self {
+        ColorNames {
+            families: vec![
+                ColorFamily::new("黒", "black", &["#000000", "#2b2b2b"]),
+                ColorFamily::new("灰", "gray", &["#505050", "#808080", "#b0b0b0"]),
+                ColorFamily::new("白", "white", &["#ffffff", "#f0f0e8"]),
+                ColorFamily::new("赤", "red", &["#ff0000", "#d0021b", "#a01010"]),
+                ColorFamily::new("橙", "orange", &["#ff8c00", "#f5a040"]),
+                ColorFamily::new("黄", "yellow", &["#ffff00", "#ffd700", "#f0e68c"]),
+                ColorFamily::new("緑", "green", &["#00a000", "#228b22", "#90c060"]),
+                ColorFamily::new("青", "blue", &["#0000ff", "#1e50c8", "#4682b4", "#87ceeb"]),
+                ColorFamily::new("紫", "purple", &["#800080", "#9370db"]),
+                ColorFamily::new("桃", "pink", &["#ff69b4", "#ffc0cb"]),
+                ColorFamily::new("茶", "brown", &["#8b4513", "#a0522d", "#d2b48c"]),
+            ],
+        }
+    }
+}
+
+impl ColorNames {
+    /// Load color families from TOML file
+    ///
+    /// ```rust
+    /// let color_names = ColorNames::from_path("./color_names.toml").unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<ColorNames, ButterflyError> {
+        let path = path.as_ref().display().to_string();
+        let content = fs::read_to_string(&path).map_err(|_e| FileNotFound(path.clone()))?;
+        let color_names: ColorNames = toml::from_str(&content)
+            .map_err(|e| FailedToParseConfig(path.clone(), e.to_string()))?;
+
+        for family in color_names.families.iter() {
+            if let Some(color) = family.colors.iter().find(|c| Lab::from_hex(c).is_none()) {
+                return Err(FailedToParseConfig(
+                    path,
+                    format!("Invalid color {} of {}", color, family.eng_name),
+                ));
+            }
+        }
+        Ok(color_names)
+    }
+
+    /// Name the family closest to `hex_color`
+    pub fn classify(&self, hex_color: &str) -> Option<ColorName> {
+        let lab = Lab::from_hex(hex_color)?;
+        let mut best: Option<(&ColorFamily, f32)> = None;
+
+        for family in self.families.iter() {
+            for reference in family.colors.iter().filter_map(|c| Lab::from_hex(c)) {
+                let distance = lab.distance(&reference);
+                if best.map(|(_, d)| distance < d).unwrap_or(true) {
+                    best = Some((family, distance));
+                }
+            }
+        }
+
+        best.map(|(family, _)| family.name())
+    }
+
+    /// Set `family` of each of `colors`
+    pub(crate) fn name_colors(&self, colors: &mut [Color]) {
+        for color in colors.iter_mut() {
+            color.family = self.classify(&color.hex_color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_named_by_closest_family() {
+        let color_names = ColorNames::default();
+        let name = |hex| color_names.classify(hex).unwrap().eng_name;
+
+        assert_eq!(name("#050505"), "black");
+        assert_eq!(name("#fdfdfd"), "white");
+        assert_eq!(name("#ffe000"), "yellow");
+        assert_eq!(name("#2040a0"), "blue");
+        assert_eq!(name("#c8141e"), "red");
+        assert_eq!(name("#7a4a20"), "brown");
+        assert_eq!(color_names.classify("#2040a0").unwrap().jp_name, "青");
+        assert_eq!(color_names.classify("not a color"), None);
+    }
+
+    #[test]
+    fn color_name_matches_either_language() {
+        let name = ColorNames::default().classify("#0000ff").unwrap();
+
+        assert!(name.matches("青"));
+        assert!(name.matches("Blue"));
+        assert!(!name.matches("赤"));
+    }
+}
@@ -0,0 +1,191 @@
+//! # Color Space
+//!
+//! This module exports `Lab`, a color in CIELAB space, which is used to compare
+//! colors the way they are perceived.
+
+/// Color in CIELAB space under D65 illuminant
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Lab {
+    /// Lightness, from 0 to 100
+    pub l: f32,
+    /// Green to red
+    pub a: f32,
+    /// Blue to yellow
+    pub b: f32,
+}
+
+// Reference white of D65 illuminant
+const WHITE_X: f32 = 0.950_47;
+const WHITE_Y: f32 = 1.0;
+const WHITE_Z: f32 = 1.088_83;
+
+impl Lab {
+    /// Convert sRGB color into `Lab`
+    pub fn from_rgb(rgb: [u8; 3]) -> Lab {
+        let linear = |c: u8| {
+            let c = f32::from(c) / 255.0;
+            if c <= 0.040_45 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let (r, g, b) = (linear(rgb[0]), linear(rgb[1]), linear(rgb[2]));
+
+        let x = 0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b;
+        let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+        let z = 0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b;
+
+        let f = |t: f32| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(x / WHITE_X), f(y / WHITE_Y), f(z / WHITE_Z));
+
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    /// Convert 6 digit hex string such as `#ffcc00` into `Lab`
+    pub fn from_hex(hex_color: &str) -> Option<Lab> {
+        parse_hex(hex_color).map(Lab::from_rgb)
+    }
+
+    /// Euclidean distance to `other`, also known as CIE76 color difference
+    pub fn distance(&self, other: &Lab) -> f32 {
+        ((self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2))
+            .sqrt()
+    }
+
+    /// CIEDE2000 color difference to `other`, which follows perceived
+    /// differences more closely than `distance`
+    pub fn ciede2000(&self, other: &Lab) -> f32 {
+        let (l1, a1, b1) = (f64::from(self.l), f64::from(self.a), f64::from(self.b));
+        let (l2, a2, b2) = (f64::from(other.l), f64::from(other.a), f64::from(other.b));
+        let pow7 = |x: f64| x.powi(7);
+        let twenty_five_pow7 = pow7(25.0);
+
+        let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+        let g = 0.5 * (1.0 - (pow7(c_bar) / (pow7(c_bar) + twenty_five_pow7)).sqrt());
+        let (a1p, a2p) = ((1.0 + g) * a1, (1.0 + g) * a2);
+        let (c1p, c2p) = ((a1p * a1p + b1 * b1).sqrt(), (a2p * a2p + b2 * b2).sqrt());
+
+        let hue = |b: f64, a: f64| {
+            if b == 0.0 && a == 0.0 {
+                0.0
+            } else {
+                b.atan2(a).to_degrees().rem_euclid(360.0)
+            }
+        };
+        let (h1p, h2p) = (hue(b1, a1p), hue(b2, a2p));
+
+        let delta_lp = l2 - l1;
+        let delta_cp = c2p - c1p;
+        let delta_hp = if c1p * c2p == 0.0 {
+            0.0
+        } else if (h2p - h1p).abs() <= 180.0 {
+            h2p - h1p
+        } else if h2p - h1p > 180.0 {
+            h2p - h1p - 360.0
+        } else {
+            h2p - h1p + 360.0
+        };
+        let delta_big_hp = 2.0 * (c1p * c2p).sqrt() * (delta_hp / 2.0).to_radians().sin();
+
+        let l_bar_p = (l1 + l2) / 2.0;
+        let c_bar_p = (c1p + c2p) / 2.0;
+        let h_bar_p = if c1p * c2p == 0.0 {
+            h1p + h2p
+        } else if (h1p - h2p).abs() <= 180.0 {
+            (h1p + h2p) / 2.0
+        } else if h1p + h2p < 360.0 {
+            (h1p + h2p + 360.0) / 2.0
+        } else {
+            (h1p + h2p - 360.0) / 2.0
+        };
+
+        let cos_deg = |d: f64| d.to_radians().cos();
+        let t = 1.0 - 0.17 * cos_deg(h_bar_p - 30.0)
+            + 0.24 * cos_deg(2.0 * h_bar_p)
+            + 0.32 * cos_deg(3.0 * h_bar_p + 6.0)
+            - 0.20 * cos_deg(4.0 * h_bar_p - 63.0);
+        let delta_theta = 30.0 * (-((h_bar_p - 275.0) / 25.0).powi(2)).exp();
+        let r_c = 2.0 * (pow7(c_bar_p) / (pow7(c_bar_p) + twenty_five_pow7)).sqrt();
+        let s_l = 1.0 + 0.015 * (l_bar_p - 50.0).powi(2) / (20.0 + (l_bar_p - 50.0).powi(2)).sqrt();
+        let s_c = 1.0 + 0.045 * c_bar_p;
+        let s_h = 1.0 + 0.015 * c_bar_p * t;
+        let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+        let (l_term, c_term, h_term) = (delta_lp / s_l, delta_cp / s_c, delta_big_hp / s_h);
+        (l_term * l_term + c_term * c_term + h_term * h_term + r_t * c_term * h_term).sqrt() as f32
+    }
+}
+
+/// Parse 6 digit hex string such as `#ffcc00` into RGB
+pub(crate) fn parse_hex(hex_color: &str) -> Option<[u8; 3]> {
+    let bytes = hex::decode(hex_color.trim().trim_start_matches('#')).ok()?;
+    match bytes.as_slice() {
+        [r, g, b] => Some([*r, *g, *b]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb_is_converted_into_lab() {
+        let white = Lab::from_hex("#ffffff").unwrap();
+        let red = Lab::from_hex("#ff0000").unwrap();
+
+        assert!((white.l - 100.0).abs() < 0.01);
+        assert!(white.a.abs() < 0.01 && white.b.abs() < 0.01);
+        // Reference values of sRGB red
+        assert!((red.l - 53.24).abs() < 0.05);
+        assert!((red.a - 80.09).abs() < 0.05);
+        assert!((red.b - 67.20).abs() < 0.05);
+        assert_eq!(Lab::from_hex("#fff"), None);
+    }
+
+    #[test]
+    fn ciede2000_matches_reference_data() {
+        // Test data of Sharma, Wu and Dalal (2005)
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, 3.1571, -77.2803), (50.0, 0.0, -82.7485), 2.8615),
+            ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+            (
+                (60.2574, -34.0099, 36.2677),
+                (60.4626, -34.1751, 39.4387),
+                1.2644,
+            ),
+            (
+                (2.0776, 0.0795, -1.1350),
+                (0.9033, -0.0636, -0.5514),
+                0.9082,
+            ),
+        ];
+
+        for ((l1, a1, b1), (l2, a2, b2), expected) in pairs.iter() {
+            let lab1 = Lab {
+                l: *l1,
+                a: *a1,
+                b: *b1,
+            };
+            let lab2 = Lab {
+                l: *l2,
+                a: *a2,
+                b: *b2,
+            };
+            assert!((lab1.ciede2000(&lab2) - expected).abs() < 1e-3);
+            assert!((lab2.ciede2000(&lab1) - expected).abs() < 1e-3);
+        }
+    }
+}
@@ -1,16 +1,22 @@
 /// Url of the website
 pub const BUTTERFLY_URL: &str = "http://biokite.com/worldbutterfly/";
+/// Url of the index page which links to every region page
+pub const INDEX_URL: &str = "http://biokite.com/worldbutterfly/butterfly-index.htm";
 /// Directory which stores the downloaded files
 pub const ASSET_DIRECTORY: &str = "./assets";
 /// Directory which stores the images
 pub const IMAGE_DIRECTORY: &str = "images";
 /// Directory which store the pdf files
 pub const PDF_DIRECTORY: &str = "pdf";
-/// Number of threads used for fetching google cloud vision api
-pub const GCV_THEAD_POOL_NUM: u32 = 30;
+/// Number of region pages fetched at the same time
+pub const REGION_THREAD_POOL_NUM: usize = 4;
+/// Smallest pixel fraction of a color for a butterfly to be found by its family
+pub const MIN_NAMED_COLOR_FRACTION: f32 = 0.05;
 /// Path to CSV file
 pub const CSV_FILE_PATH: &str = "./butterfly.csv";
 /// Google Cloud Vision API
 pub const CLOUD_VISION_URI: &str = "https://vision.googleapis.com/v1/images:annotate";
-/// Path to API key
+/// Directory which stores the responses of Google Cloud Vision API
+pub const CLOUD_VISION_CACHE_DIRECTORY: &str = "./assets/cloud_vision_cache";
+/// Default path to API key
 pub const API_KEY_FILE_PATH: &str = "./secrets/vision_api.key";
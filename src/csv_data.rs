@@ -1,10 +1,15 @@
 use csv::StringRecord;
 use kana;
+use log::warn;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
 
+use super::butterfly::Butterfly;
+use super::butterfly_id::{name_key, normalize_id_name};
 use super::constants::*;
 use super::errors::ButterflyError::{self, *};
+use super::scientific_name::{ScientificName, Sex};
 
 /// CSV data extracted from `butterfly.csv`
 #[derive(Debug, PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
@@ -13,6 +18,120 @@ pub struct CSVData {
     pub open_length: u32,
     pub diet: Option<String>,
     pub remarks: Option<String>,
+    /// Link to pdf file
+    #[serde(default)]
+    pub pdf_link: Option<String>,
+}
+
+/// Header names of the columns read from CSV file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvColumns {
+    /// English name
+    pub eng_name: String,
+    /// Japanese name
+    pub jp_name: String,
+    /// Length of the butterfly
+    pub open_length: String,
+    /// Distribution
+    pub distribution: String,
+    /// Food they eat, not read if `None`
+    pub diet: Option<String>,
+    /// Remarks, not read if `None`
+    pub remarks: Option<String>,
+    /// Link to pdf file, not read if `None`
+    pub pdf_link: Option<String>,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        CsvColumns {
+            eng_name: "学名".to_string(),
+            jp_name: "和名".to_string(),
+            open_length: "開長(mm)".to_string(),
+            distribution: "分布".to_string(),
+            diet: Some("食草".to_string()),
+            remarks: Some("メモ".to_string()),
+            pdf_link: Some("Pdfリンク".to_string()),
+        }
+    }
+}
+
+impl CsvColumns {
+    /// Set the header name of `field`, such as `eng_name` or `remarks`
+    pub fn set(&mut self, field: &str, header: &str) -> Result<(), ButterflyError> {
+        let header = header.to_string();
+        match field {
+            "eng_name" => self.eng_name = header,
+            "jp_name" => self.jp_name = header,
+            "open_length" => self.open_length = header,
+            "distribution" => self.distribution = header,
+            "diet" => self.diet = Some(header),
+            "remarks" => self.remarks = Some(header),
+            "pdf_link" => self.pdf_link = Some(header),
+            _ => return Err(UnknownCsvField(field.to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// Location and columns of the CSV file used to enrich butterflies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvConfig {
+    /// Path to CSV file
+    pub path: PathBuf,
+    /// Header names of the columns
+    pub columns: CsvColumns,
+}
+
+impl Default for CsvConfig {
+    fn default() -> Self {
+        CsvConfig {
+            path: PathBuf::from(CSV_FILE_PATH),
+            columns: CsvColumns::default(),
+        }
+    }
+}
+
+/// Positions of `CsvColumns` in the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ColumnIndices {
+    eng_name: usize,
+    jp_name: usize,
+    open_length: usize,
+    distribution: usize,
+    diet: Option<usize>,
+    remarks: Option<usize>,
+    pdf_link: Option<usize>,
+}
+
+impl ColumnIndices {
+    /// Find the position of each column in `headers`
+    pub(crate) fn new(
+        headers: &StringRecord,
+        columns: &CsvColumns,
+        path: &str,
+    ) -> Result<ColumnIndices, ButterflyError> {
+        let find = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim() == name.trim())
+                .ok_or_else(|| CsvColumnNotFound(path.to_string(), name.to_string()))
+        };
+        let find_optional = |name: &Option<String>| match name {
+            Some(name) => find(name).map(Some),
+            None => Ok(None),
+        };
+
+        Ok(ColumnIndices {
+            eng_name: find(&columns.eng_name)?,
+            jp_name: find(&columns.jp_name)?,
+            open_length: find(&columns.open_length)?,
+            distribution: find(&columns.distribution)?,
+            diet: find_optional(&columns.diet)?,
+            remarks: find_optional(&columns.remarks)?,
+            pdf_link: find_optional(&columns.pdf_link)?,
+        })
+    }
 }
 
 #[derive(Eq, Debug, PartialEq, Hash, Clone)]
@@ -23,36 +142,46 @@ pub struct EngName(pub String);
 
 impl CSVData {
     /// Create an new instance of `CSVData`
-    pub(crate) fn new(vec: StringRecord) -> Option<((JPName, EngName), CSVData)> {
-        let eng_name = vec.get(0)?;
-        let jp_name = vec.get(1)?;
-        let open_length = vec.get(3).and_then(|num| {
+    pub(crate) fn new(
+        vec: StringRecord,
+        indices: &ColumnIndices,
+    ) -> Option<((JPName, EngName), CSVData)> {
+        let eng_name = vec.get(indices.eng_name)?;
+        let jp_name = vec.get(indices.jp_name)?;
+        let open_length = vec.get(indices.open_length).and_then(|num| {
             let parsed: Option<u32> = num.parse().ok();
             parsed
         })?;
 
-        let distribution = vec.get(4).map(|v| normalize(v))?;
+        let distribution = vec.get(indices.distribution).map(|v| normalize(v))?;
 
-        let diet = vec.get(5).and_then(|d| {
+        let diet = indices.diet.and_then(|i| vec.get(i)).and_then(|d| {
             if d.is_empty() {
                 None
             } else {
                 Some(normalize(d))
             }
         });
-        let remarks = vec.get(6).and_then(|r| {
+        let remarks = indices.remarks.and_then(|i| vec.get(i)).and_then(|r| {
             if r.is_empty() {
                 None
             } else {
                 Some(normalize(r))
             }
         });
+        let pdf_link = indices
+            .pdf_link
+            .and_then(|i| vec.get(i))
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_string());
 
         let csv_data = CSVData {
             distribution,
             open_length,
             diet,
             remarks,
+            pdf_link,
         };
 
         Some((
@@ -62,28 +191,195 @@ impl CSVData {
     }
 }
 
-/// Fetch `CSVData` from CSV file in which the filepath is `CSV_FILE_PATH`
-pub(crate) fn fetch_csv_data() -> Result<HashMap<(JPName, EngName), CSVData>, ButterflyError> {
+/// Fetch `CSVData` from CSV file given by `config`
+pub(crate) fn fetch_csv_data(
+    config: &CsvConfig,
+) -> Result<HashMap<(JPName, EngName), CSVData>, ButterflyError> {
+    let path = config.path.display().to_string();
     let mut csv_data_map = HashMap::new();
     // Read file
-    let mut cvs_file_content = csv::Reader::from_path(CSV_FILE_PATH)
-        .map_err(|_e| FileNotFound(CSV_FILE_PATH.to_owned()))?;
+    let mut cvs_file_content =
+        csv::Reader::from_path(&config.path).map_err(|_e| FileNotFound(path.to_owned()))?;
+    let headers = cvs_file_content
+        .headers()
+        .map_err(|_e| FailedToParseCSVRecord(path.to_owned()))?;
+    let indices = ColumnIndices::new(headers, &config.columns, &path)?;
 
     for record in cvs_file_content.records() {
-        let record =
-            record.or_else(|_err| Err(FailedToParseCSVRecord(CSV_FILE_PATH.to_string())))?;
-        if let Some((key, csv_data)) = CSVData::new(record) {
+        let record = record.or_else(|_err| Err(FailedToParseCSVRecord(path.to_owned())))?;
+        if let Some((key, csv_data)) = CSVData::new(record, &indices) {
             csv_data_map.insert(key, csv_data);
         } else {
-            return Err(FailedToParseCSVRecord(CSV_FILE_PATH.to_string()));
+            return Err(FailedToParseCSVRecord(path.to_owned()));
         };
     }
 
     Ok(csv_data_map)
 }
 
+/// Find the row of `csv_data_map` matching each of `butterflies`
+///
+/// Rows are matched by the names of the butterfly, or by the normalized
+/// scientific name used in `ButterflyId` when the names differ slightly.
+/// Several rows may share a scientific name, in which case the row whose
+/// Japanese name and sex are the same as the butterfly is used. Butterflies
+/// are left unmatched when no single row is found this way.
+pub(crate) fn match_csv_rows<'a>(
+    butterflies: &[Butterfly],
+    csv_data_map: &'a HashMap<(JPName, EngName), CSVData>,
+) -> Vec<Option<&'a (JPName, EngName)>> {
+    let mut keys_by_name: HashMap<String, Vec<&(JPName, EngName)>> = HashMap::new();
+    for key in csv_data_map.keys() {
+        let (jp_name, eng_name) = key;
+        let scientific_name = ScientificName::parse(&eng_name.0).ok();
+        keys_by_name
+            .entry(name_key(scientific_name.as_ref(), &eng_name.0, &jp_name.0))
+            .or_default()
+            .push(key);
+    }
+
+    butterflies
+        .iter()
+        .map(|butterfly| {
+            let key = (
+                JPName(butterfly.jp_name.to_owned()),
+                EngName(butterfly.eng_name.to_owned()),
+            );
+            match csv_data_map.get_key_value(&key) {
+                Some((key, _)) => Some(key),
+                None => {
+                    let name = name_key(
+                        butterfly.scientific_name.as_ref(),
+                        &butterfly.eng_name,
+                        &butterfly.jp_name,
+                    );
+                    let keys = keys_by_name.get(&name).map(|keys| keys.as_slice());
+                    single_row(butterfly, keys.unwrap_or(&[]))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Row among `keys` sharing the scientific name of `butterfly`, if only one
+/// of them can be told to match it
+fn single_row<'a>(
+    butterfly: &Butterfly,
+    keys: &[&'a (JPName, EngName)],
+) -> Option<&'a (JPName, EngName)> {
+    match keys {
+        [] => return None,
+        [key] => return Some(key),
+        _ => {}
+    }
+
+    let jp_name = normalize_id_name(&butterfly.jp_name);
+    let candidates = keys
+        .iter()
+        .filter(|(row_jp_name, row_eng_name)| {
+            let sex =
+                Sex::from_marker(&row_jp_name.0).or_else(|| Sex::from_marker(&row_eng_name.0));
+            normalize_id_name(&row_jp_name.0) == jp_name && sex == butterfly.sex
+        })
+        .collect::<Vec<_>>();
+
+    match candidates.as_slice() {
+        [key] => Some(key),
+        _ => {
+            let mut rows = keys
+                .iter()
+                .map(|(jp_name, eng_name)| format!("{} ({})", jp_name.0, eng_name.0))
+                .collect::<Vec<_>>();
+            rows.sort();
+            warn!(
+                "Ambiguous csv rows for {}: {}",
+                butterfly.jp_name,
+                rows.join(", ")
+            );
+            None
+        }
+    }
+}
+
 pub(crate) fn normalize(text: &str) -> String {
     let result = kana::wide2ascii(text);
     let result = kana::nowidespace(&result);
     kana::half2kana(&result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn columns_are_found_by_header_name() {
+        let path = env::temp_dir().join("butterfly_extractor_csv_columns.csv");
+        fs::write(
+            &path,
+            "和名,分布,学名,開長(mm),備考,Pdfリンク\nシロメダマワモン,PapuaNewGuinea,Taenaris_captos,106,ﾒﾀﾞﾏﾁｮｳ属, butterfly_pdf/i_o/i_o-am_1.pdf \n",
+        )
+        .unwrap();
+
+        let mut config = CsvConfig {
+            path: path.clone(),
+            ..CsvConfig::default()
+        };
+        config.columns.diet = None;
+        config.columns.set("remarks", "備考").unwrap();
+        let csv_data_map = fetch_csv_data(&config).unwrap();
+        let csv_data = &csv_data_map[&(
+            JPName("シロメダマワモン".to_string()),
+            EngName("Taenaris_captos".to_string()),
+        )];
+        assert_eq!(csv_data.open_length, 106);
+        assert_eq!(csv_data.distribution, "PapuaNewGuinea");
+        assert_eq!(csv_data.remarks.as_deref(), Some("メダマチョウ属"));
+        assert_eq!(csv_data.diet, None);
+        assert_eq!(
+            csv_data.pdf_link.as_deref(),
+            Some("butterfly_pdf/i_o/i_o-am_1.pdf")
+        );
+
+        config.columns.diet = Some("食草".to_string());
+        assert!(matches!(
+            fetch_csv_data(&config),
+            Err(CsvColumnNotFound(_, column)) if column == "食草"
+        ));
+        assert!(config.columns.set("wingspan", "開長").is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rows_sharing_a_scientific_name_are_matched_by_japanese_name() {
+        let csv_data = CSVData {
+            distribution: String::new(),
+            open_length: 0,
+            diet: None,
+            remarks: None,
+            pdf_link: None,
+        };
+        let key = |jp_name: &str, eng_name: &str| {
+            (JPName(jp_name.to_string()), EngName(eng_name.to_string()))
+        };
+        let csv_data_map = vec![
+            (key("キアゲハ", "Papilio_machaon"), csv_data.clone()),
+            (key("ワモンチョウ", "Zeuxidia_aurelius"), csv_data.clone()),
+            (key("オオルリフクロウ", "Zeuxidia_aurelius"), csv_data),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        let butterflies = vec![
+            Butterfly::named("東洋区", "キアゲハ", "Papilio machaon"),
+            Butterfly::named("東洋区", "オオルリフクロウ", "Zeuxidia aurelius"),
+            Butterfly::named("東洋区", "オオルリフクロウ♀", "Zeuxidia aurelius"),
+            Butterfly::named("東洋区", "ルリフクロウ", "Zeuxidia aurelius"),
+        ];
+
+        let keys = match_csv_rows(&butterflies, &csv_data_map);
+        assert_eq!(keys[0], Some(&key("キアゲハ", "Papilio_machaon")));
+        assert_eq!(keys[1], Some(&key("オオルリフクロウ", "Zeuxidia_aurelius")));
+        assert_eq!(keys[2], None);
+        assert_eq!(keys[3], None);
+    }
+}
@@ -0,0 +1,275 @@
+//! # CSV Report
+//!
+//! This module exports `CsvReport`, which reconciles the butterflies found on
+//! the website with the rows of the CSV file. It lists butterflies missing
+//! from the CSV file, rows which match no butterfly, and butterflies whose pdf
+//! differs from the link in the CSV file.
+
+use kanaria::UCSStr;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+
+use super::butterfly::Butterfly;
+use super::butterfly_id::ButterflyId;
+use super::constants::BUTTERFLY_URL;
+use super::csv_data::{match_csv_rows, CSVData, EngName, JPName};
+
+/// Kind of disagreement between the website and the CSV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvIssueKind {
+    /// Butterfly on the website has no row in the CSV file
+    MissingInCsv,
+    /// Pdf of the butterfly differs from the link in the CSV file
+    PdfMismatch,
+    /// Row of the CSV file matches no butterfly on the website
+    UnmatchedCsvRow,
+}
+
+/// Disagreement between the website and the CSV file
+///
+/// Fields which do not apply to `kind` are left empty.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CsvIssue {
+    /// Kind of the disagreement
+    pub kind: CsvIssueKind,
+    /// Japanese name
+    pub jp_name: String,
+    /// English name
+    pub eng_name: String,
+    /// Id of the butterfly on the website
+    pub id: Option<ButterflyId>,
+    /// Region of the butterfly on the website
+    pub region: Option<String>,
+    /// Url of the pdf on the website
+    pub site_pdf: Option<String>,
+    /// Url of the pdf in the CSV file
+    pub csv_pdf: Option<String>,
+}
+
+/// Result of reconciling the website with the CSV file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvReport {
+    /// Disagreements, sorted by kind and name
+    pub issues: Vec<CsvIssue>,
+}
+
+impl CsvReport {
+    /// Reconcile `butterflies` with `csv_data_map`
+    pub(crate) fn new(
+        butterflies: &[Butterfly],
+        csv_data_map: &HashMap<(JPName, EngName), CSVData>,
+    ) -> CsvReport {
+        let keys = match_csv_rows(butterflies, csv_data_map);
+        let mut matched: HashSet<&(JPName, EngName)> = HashSet::new();
+        let mut issues = Vec::new();
+
+        for (butterfly, key) in butterflies.iter().zip(keys) {
+            let issue = |kind, csv_pdf: Option<String>| CsvIssue {
+                kind,
+                jp_name: butterfly.jp_name.to_owned(),
+                eng_name: butterfly.eng_name.to_owned(),
+                id: Some(butterfly.id.to_owned()),
+                region: Some(butterfly.region.to_owned()),
+                site_pdf: pdf_url(&butterfly.pdf_src),
+                csv_pdf,
+            };
+
+            let key = match key {
+                Some(key) => key,
+                None => {
+                    issues.push(issue(CsvIssueKind::MissingInCsv, None));
+                    continue;
+                }
+            };
+            matched.insert(key);
+
+            if let Some(pdf_link) = &csv_data_map[key].pdf_link {
+                let csv_pdf = pdf_url(pdf_link);
+                if csv_pdf != pdf_url(&butterfly.pdf_src) {
+                    issues.push(issue(CsvIssueKind::PdfMismatch, csv_pdf));
+                }
+            }
+        }
+
+        for (key, csv_data) in csv_data_map.iter() {
+            if !matched.contains(key) {
+                let (jp_name, eng_name) = key;
+                issues.push(CsvIssue {
+                    kind: CsvIssueKind::UnmatchedCsvRow,
+                    jp_name: jp_name.0.to_owned(),
+                    eng_name: eng_name.0.to_owned(),
+                    id: None,
+                    region: None,
+                    site_pdf: None,
+                    csv_pdf: csv_data.pdf_link.as_deref().and_then(pdf_url),
+                });
+            }
+        }
+
+        issues.sort_by(|i1, i2| {
+            (i1.kind, &i1.jp_name, &i1.region).cmp(&(i2.kind, &i2.jp_name, &i2.region))
+        });
+        CsvReport { issues }
+    }
+
+    /// Issues of given kind
+    pub fn issues_of(&self, kind: CsvIssueKind) -> Vec<&CsvIssue> {
+        self.issues.iter().filter(|i| i.kind == kind).collect()
+    }
+
+    /// Check if the website and the CSV file agree
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Store the report as JSON file
+    pub fn store_json(&self, file_path: &str) -> Result<(), std::io::Error> {
+        let json_file = File::create(file_path)?;
+        serde_json::to_writer_pretty(json_file, self)?;
+        Ok(())
+    }
+
+    /// Store the issues as CSV file, one issue per row
+    pub fn store_csv(&self, file_path: &str) -> Result<(), std::io::Error> {
+        let mut writer = csv::Writer::from_path(file_path)?;
+        for issue in self.issues.iter() {
+            writer.serialize(issue)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Absolute url of a pdf given either as an url or a path relative to
+/// `BUTTERFLY_URL`, so that links written differently can be compared
+fn pdf_url(link: &str) -> Option<String> {
+    let link = UCSStr::from_str(link.trim()).narrow().to_string();
+    if link.is_empty() {
+        return None;
+    }
+    Url::parse(BUTTERFLY_URL)
+        .and_then(|base| base.join(&link))
+        .map(|url| url.to_string())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv_row(jp_name: &str, eng_name: &str, pdf_link: &str) -> ((JPName, EngName), CSVData) {
+        (
+            (JPName(jp_name.to_string()), EngName(eng_name.to_string())),
+            CSVData {
+                distribution: String::new(),
+                open_length: 0,
+                diet: None,
+                remarks: None,
+                pdf_link: Some(pdf_link.to_string()),
+            },
+        )
+    }
+
+    #[test]
+    fn website_and_csv_are_reconciled() {
+        let butterflies = vec![
+            Butterfly {
+                pdf_src: "butterfly_pdf/p/p-pa_1.pdf".to_string(),
+                ..Butterfly::named("旧北区", "キアゲハ", "Papilio_machaon")
+            },
+            Butterfly {
+                pdf_src: "butterfly_pdf/p/p-pa_2.pdf".to_string(),
+                ..Butterfly::named("旧北区", "ナミアゲハ", "Papilio_xuthus")
+            },
+            Butterfly {
+                pdf_src: "butterfly_pdf/p/p-pi_1.pdf".to_string(),
+                ..Butterfly::named("旧北区", "モンシロチョウ", "Pieris_rapae")
+            },
+        ];
+        let csv_data_map = vec![
+            csv_row(
+                "キアゲハ",
+                "Papilio_machaon",
+                "http://biokite.com/worldbutterfly/butterfly_pdf/p/p-pa_1.pdf",
+            ),
+            csv_row("ナミアゲハ", "Papilio xuthus", "butterfly_pdf/p/p-pa_3.pdf"),
+            csv_row(
+                "オオムラサキ",
+                "Sasakia_charonda",
+                "butterfly_pdf/p/p-ny_1 1.pdf",
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let report = CsvReport::new(&butterflies, &csv_data_map);
+        let names = |kind| {
+            report
+                .issues_of(kind)
+                .iter()
+                .map(|i| i.jp_name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(report.issues.len(), 3);
+        assert_eq!(names(CsvIssueKind::MissingInCsv), vec!["モンシロチョウ"]);
+        assert_eq!(names(CsvIssueKind::PdfMismatch), vec!["ナミアゲハ"]);
+        assert_eq!(names(CsvIssueKind::UnmatchedCsvRow), vec!["オオムラサキ"]);
+
+        let mismatch = &report.issues_of(CsvIssueKind::PdfMismatch)[0];
+        assert_eq!(
+            mismatch.csv_pdf.as_deref(),
+            Some("http://biokite.com/worldbutterfly/butterfly_pdf/p/p-pa_3.pdf")
+        );
+        assert_eq!(
+            report.issues_of(CsvIssueKind::UnmatchedCsvRow)[0]
+                .csv_pdf
+                .as_deref(),
+            Some("http://biokite.com/worldbutterfly/butterfly_pdf/p/p-ny_1%201.pdf")
+        );
+    }
+
+    #[test]
+    fn rows_sharing_a_scientific_name_are_not_mixed_up() {
+        let butterflies = vec![
+            Butterfly {
+                pdf_src: "butterfly_pdf/i_o/i_o-am_1 2.pdf".to_string(),
+                ..Butterfly::named("東洋区", "オオルリフクロウ", "Zeuxidia aurelius")
+            },
+            Butterfly {
+                pdf_src: "butterfly_pdf/i_o/i_o-am_1 3.pdf".to_string(),
+                ..Butterfly::named("東洋区", "ルリフクロウ", "Zeuxidia aurelius")
+            },
+        ];
+        let csv_data_map = vec![
+            csv_row(
+                "ワモンチョウ",
+                "Zeuxidia_aurelius",
+                "butterfly_pdf/i_o/i_o-am_1 1.pdf",
+            ),
+            csv_row(
+                "オオルリフクロウ",
+                "Zeuxidia_aurelius",
+                "butterfly_pdf/i_o/i_o-am_1 2.pdf",
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let report = CsvReport::new(&butterflies, &csv_data_map);
+        let names = |kind| {
+            report
+                .issues_of(kind)
+                .iter()
+                .map(|i| i.jp_name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(CsvIssueKind::MissingInCsv), vec!["ルリフクロウ"]);
+        assert!(names(CsvIssueKind::PdfMismatch).is_empty());
+        assert_eq!(names(CsvIssueKind::UnmatchedCsvRow), vec!["ワモンチョウ"]);
+    }
+}
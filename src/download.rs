@@ -0,0 +1,303 @@
+//! # Download
+//!
+//! This module exports `download_file` which stores assets on the disk so that
+//! an interrupted run can be resumed safely:
+//!
+//! - Files already present whose SHA-256 matches the recorded one are skipped
+//! - Files which are present but unverified are revalidated with conditional
+//!   requests (`ETag` / `Last-Modified`) when the server sent them before
+//! - Files are written to a temporary file which is renamed when complete.
+//!   Each download uses its own temporary file, so concurrent downloads of the
+//!   same path do not write into each other
+
+use log::trace;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::errors::ButterflyError;
+use super::http_client::HttpClient;
+
+// Extension of the file being downloaded
+const PARTIAL_EXTENSION: &str = "part";
+// Extension of the file storing the validators sent by the server
+const VALIDATORS_EXTENSION: &str = "http.json";
+
+// Number of temporary files created by this process, used to name them
+static PARTIAL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// File stored on the disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Download {
+    /// Path to the file
+    pub path: String,
+    /// SHA-256 of the content in hex
+    pub sha256: String,
+}
+
+/// Validators sent by the server, used for conditional requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Validators {
+        let value = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        Validators {
+            etag: value(ETAG),
+            last_modified: value(LAST_MODIFIED),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    fn to_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = self
+            .etag
+            .as_ref()
+            .and_then(|v| HeaderValue::from_str(v).ok())
+        {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(modified) = self
+            .last_modified
+            .as_ref()
+            .and_then(|v| HeaderValue::from_str(v).ok())
+        {
+            headers.insert(IF_MODIFIED_SINCE, modified);
+        }
+        headers
+    }
+}
+
+///Fetch file from biokite.com and store them on a directory
+///
+/// `known_sha256` is the hash recorded by a previous run, if any. The file is
+/// not requested at all when the one on the disk still has that hash.
+///
+/// Will return `Error` type if,
+///
+/// 1. File could not be fetched (either connnection issue or status code other than `Ok`)
+/// 2. File could not be created
+/// 3. Writing to file failed
+pub(crate) fn download_file(
+    http: &HttpClient,
+    file_path: &PathBuf,
+    url: Url,
+    known_sha256: Option<&str>,
+) -> anyhow::Result<Download> {
+    let path = file_path.to_str().unwrap().to_string();
+    let validators_path = with_extension(file_path, VALIDATORS_EXTENSION);
+
+    let existing_sha256 = if file_path.exists() {
+        Some(sha256_file(file_path)?)
+    } else {
+        None
+    };
+
+    if let (Some(existing), Some(known)) = (&existing_sha256, known_sha256) {
+        if existing == known {
+            trace!("Already downloaded: {:#?}", file_path);
+            return Ok(Download {
+                path,
+                sha256: existing.to_owned(),
+            });
+        }
+    }
+
+    // Only revalidate files which are on the disk
+    let validators = match existing_sha256 {
+        Some(_) => read_validators(&validators_path),
+        None => Validators::default(),
+    };
+
+    let mut response = http.get_with_headers(url, validators.to_headers())?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(sha256) = existing_sha256 {
+            trace!("Not modified: {:#?}", file_path);
+            return Ok(Download { path, sha256 });
+        }
+    }
+
+    if response.status() != StatusCode::OK {
+        anyhow::bail!(ButterflyError::FileNotFound(path));
+    }
+
+    let partial_path = partial_path(file_path);
+    let written = write_file(&partial_path, &mut response)
+        .and_then(|sha256| fs::rename(&partial_path, file_path).map(|_| sha256));
+    if written.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+    let sha256 = written?;
+
+    let new_validators = Validators::from_headers(response.headers());
+    if new_validators.is_empty() {
+        let _ = fs::remove_file(&validators_path);
+    } else {
+        fs::write(&validators_path, serde_json::to_vec(&new_validators)?)?;
+    }
+
+    trace!("Downloaded: {:#?}", file_path);
+    Ok(Download { path, sha256 })
+}
+
+/// Compute SHA-256 of the file on `path` in hex
+pub(crate) fn sha256_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.input(&buf[..read]);
+    }
+    Ok(hex::encode(hasher.result()))
+}
+
+/// Compute SHA-256 of `bytes` in hex
+pub(crate) fn sha256_bytes(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Append `extension` to the file name of `path`
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
+
+/// Write everything read from `reader` to `path`, returning SHA-256 of it in hex
+fn write_file<R: Read>(path: &Path, reader: &mut R) -> io::Result<String> {
+    let mut out = HashingWriter::new(File::create(path)?);
+    io::copy(reader, &mut out)?;
+    out.finish()
+}
+
+/// Temporary file in the directory of `path`, unique to this download
+fn partial_path(path: &Path) -> PathBuf {
+    let count = PARTIAL_COUNT.fetch_add(1, Ordering::SeqCst);
+    with_extension(
+        path,
+        &format!("{}-{}.{}", process::id(), count, PARTIAL_EXTENSION),
+    )
+}
+
+fn read_validators(path: &Path) -> Validators {
+    fs::read(path)
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Writer which computes SHA-256 of everything written to it
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Flush the writer and return the hash in hex
+    fn finish(mut self) -> io::Result<String> {
+        self.inner.flush()?;
+        Ok(hex::encode(self.hasher.result()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.input(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // sha256 of "butterfly"
+    const BUTTERFLY_SHA256: &str =
+        "fcfd075cbe367c158d5cfaa31fa06656a3e68f626388d96ee81b35dda4310b58";
+
+    #[test]
+    fn hashing_writer_matches_file_hash() {
+        let path = env::temp_dir().join("butterfly_extractor_hashing_writer.txt");
+        let mut out = HashingWriter::new(File::create(&path).unwrap());
+        out.write_all(b"butterfly").unwrap();
+        let sha256 = out.finish().unwrap();
+
+        assert_eq!(sha256, BUTTERFLY_SHA256);
+        assert_eq!(sha256_file(&path).unwrap(), BUTTERFLY_SHA256);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_with_known_hash_is_not_requested() {
+        let path = env::temp_dir().join("butterfly_extractor_known_hash.txt");
+        fs::write(&path, b"butterfly").unwrap();
+        // Unreachable url, so the test fails if a request is made
+        let url = Url::parse("http://127.0.0.1:9/butterfly.jpg").unwrap();
+
+        let download =
+            download_file(&HttpClient::default(), &path, url, Some(BUTTERFLY_SHA256)).unwrap();
+
+        assert_eq!(download.sha256, BUTTERFLY_SHA256);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn extension_is_appended_to_file_name() {
+        let path = Path::new("./assets/old_north/images/a.jpg");
+
+        assert_eq!(
+            with_extension(path, PARTIAL_EXTENSION),
+            Path::new("./assets/old_north/images/a.jpg.part")
+        );
+    }
+
+    #[test]
+    fn partial_paths_are_unique() {
+        let path = Path::new("./assets/old_north/images/a.jpg");
+        let first = partial_path(path);
+        let second = partial_path(path);
+
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), path.parent());
+        assert!(first.to_str().unwrap().ends_with(".part"));
+    }
+}
@@ -6,12 +6,33 @@ pub enum ButterflyError {
     /// Image source was not found when extracting
     #[error("Image source not found when parsing the page")]
     ImageSourceNotFound,
+    /// Link to the pdf file was not found when extracting
+    #[error("Link to pdf file not found when parsing the page")]
+    LinkNotFound,
     /// Text data was not found when extracting
     #[error("Text description of a butterfly could not be extracted")]
     TextNotFound,
     /// Butterfly was not found
     #[error("Index of given butterfly does not exist")]
     InvalidIndexButterflyNotFound,
+    /// Too many cells could not be parsed in a region
+    #[error("Too many cells could not be parsed in {0}: {1}")]
+    TooManyParseErrors(String, usize),
+    /// None of the regions could be extracted
+    #[error("Failed to extract data from all {0} regions")]
+    NoRegionExtracted(usize),
+    /// Failed to build thread pool
+    #[error("Failed to build thread pool: {0}")]
+    FailedToBuildThreadPool(String),
+    /// Failed to build http client
+    #[error("Failed to build http client: {0}")]
+    FailedToBuildHttpClient(String),
+    /// Request could not be sent
+    #[error("Failed to send request: {0}")]
+    FailedToRequest(String),
+    /// Url is disallowed by robots.txt of the host
+    #[error("Disallowed by robots.txt: {0}")]
+    DisallowedByRobotsTxt(String),
     /// Failed to fetch html data
     #[error("Failed to fetch html: {0}")]
     FailedToFetchHTML(String),
@@ -30,7 +51,31 @@ pub enum ButterflyError {
     /// JSON file not found
     #[error("JSON file not found: {0}")]
     JsonFileNotFound(String),
+    /// Failed to parse config file
+    #[error("Failed to parse config file {0}: {1}")]
+    FailedToParseConfig(String, String),
+    /// Api key of Google Cloud Vision API could not be read
+    #[error("Cloud vision api key not found at: {0}")]
+    ApiKeyNotFound(String),
+    /// Color is not a 6 digit hex string
+    #[error("Invalid color: {0}")]
+    InvalidColor(String),
+    /// Image has not been downloaded yet
+    #[error("Image has not been downloaded: {0}")]
+    ImageNotDownloaded(String),
+    /// Failed to decode image file
+    #[error("Failed to read image {0}: {1}")]
+    FailedToReadImage(String, String),
     /// Failed to parse JSON file
     #[error("Failed to parse JSON file: {0}")]
     FailedToParseJson(String),
+    /// English name is not a scientific name in binomial form
+    #[error("Invalid scientific name {0}: {1}")]
+    InvalidScientificName(String, String),
+    /// Column was not found in the header of CSV file
+    #[error("Column {1} not found in CSV file {0}")]
+    CsvColumnNotFound(String, String),
+    /// Field of `CsvColumns` does not exist
+    #[error("Unknown CSV field: {0}")]
+    UnknownCsvField(String),
 }
@@ -0,0 +1,452 @@
+//! # Http Client
+//!
+//! This module exports `HttpClient` which is used for every request made by
+//! this crate, from fetching region pages to downloading assets and calling
+//! Google Cloud Vision API. Requests time out, and transient failures are
+//! retried with exponential backoff. Requests to each host are rate limited,
+//! and pages disallowed by the host's `robots.txt` are not fetched.
+
+use log::{info, warn};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use reqwest::{IntoUrl, RequestBuilder, Response, StatusCode, Url};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::errors::ButterflyError;
+use super::politeness::{RateLimiter, RobotsTxt};
+
+/// User agent sent with every request
+const DEFAULT_USER_AGENT: &str = concat!(
+    "butterfly_extractor/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/HirotoShioi/butterfly)"
+);
+
+/// Timeout, retry, rate limit and identification policy of `HttpClient`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpConfig {
+    /// Timeout of a single request
+    pub timeout: Duration,
+    /// Number of times a failed request is retried
+    pub retries: u32,
+    /// Wait before the first retry, doubled on each following retry
+    pub backoff: Duration,
+    /// Longest wait between retries
+    pub max_backoff: Duration,
+    /// Value of `User-Agent` header
+    pub user_agent: String,
+    /// Average number of requests per second sent to each host, `0` to disable
+    pub requests_per_second: f64,
+    /// Number of requests which can be sent to a host at once, which is also
+    /// used as the number of parallel downloads
+    pub burst: u32,
+    /// Whether pages disallowed by `robots.txt` are skipped
+    pub respect_robots_txt: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            requests_per_second: 2.0,
+            burst: 4,
+            respect_robots_txt: true,
+        }
+    }
+}
+
+/// Http client shared by every request in the crate
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    config: HttpConfig,
+    rate_limiter: Arc<RateLimiter>,
+    // Rules of robots.txt keyed by origin of the host
+    robots: Arc<Mutex<HashMap<String, RobotsTxt>>>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new(HttpConfig::default()).expect("Failed to build http client")
+    }
+}
+
+impl HttpClient {
+    /// Create an new instance of `HttpClient`
+    ///
+    /// ```rust
+    /// let http = HttpClient::new(HttpConfig {
+    ///     retries: 5,
+    ///     ..HttpConfig::default()
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn new(config: HttpConfig) -> Result<HttpClient, ButterflyError> {
+        let user_agent = HeaderValue::from_str(&config.user_agent)
+            .map_err(|_e| ButterflyError::FailedToBuildHttpClient(config.user_agent.clone()))?;
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, user_agent);
+
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .default_headers(headers)
+            .build()
+            .map_err(|e| ButterflyError::FailedToBuildHttpClient(e.to_string()))?;
+
+        let rate_limiter = Arc::new(RateLimiter::new(config.requests_per_second, config.burst));
+
+        Ok(HttpClient {
+            client,
+            config,
+            rate_limiter,
+            robots: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Policy used by this client
+    pub fn config(&self) -> &HttpConfig {
+        &self.config
+    }
+
+    /// Number of requests worth running in parallel under the rate limit
+    pub fn parallelism(&self) -> u32 {
+        self.config.burst.max(1)
+    }
+
+    /// Send GET request to `url`, unless it is disallowed by `robots.txt`
+    pub(crate) fn get<U: IntoUrl>(&self, url: U) -> Result<Response, ButterflyError> {
+        self.get_with_headers(url, HeaderMap::new())
+    }
+
+    /// Send GET request to `url` with additional `headers`, unless it is
+    /// disallowed by `robots.txt`
+    pub(crate) fn get_with_headers<U: IntoUrl>(
+        &self,
+        url: U,
+        headers: HeaderMap,
+    ) -> Result<Response, ButterflyError> {
+        let url = url
+            .into_url()
+            .map_err(|e| ButterflyError::FailedToRequest(e.to_string()))?;
+
+        if self.config.respect_robots_txt && !self.is_allowed(&url) {
+            return Err(ButterflyError::DisallowedByRobotsTxt(url.to_string()));
+        }
+
+        self.send(&url, |client| {
+            client.get(url.clone()).headers(headers.clone())
+        })
+        .map_err(|e| ButterflyError::FailedToRequest(e.to_string()))
+    }
+
+    /// Send POST request to `url` with given query and JSON body
+    pub(crate) fn post_json<U, Q, B>(
+        &self,
+        url: U,
+        query: &Q,
+        body: &B,
+    ) -> Result<Response, ButterflyError>
+    where
+        U: IntoUrl,
+        Q: Serialize + ?Sized,
+        B: Serialize + ?Sized,
+    {
+        let url = url
+            .into_url()
+            .map_err(|e| ButterflyError::FailedToRequest(e.to_string()))?;
+
+        self.send(&url, |client| {
+            client.post(url.clone()).query(query).json(body)
+        })
+        .map_err(|e| ButterflyError::FailedToRequest(e.to_string()))
+    }
+
+    /// Check `robots.txt` of the host of `url`, fetching it on first use
+    fn is_allowed(&self, url: &Url) -> bool {
+        let origin = url.origin().ascii_serialization();
+        if let Some(rules) = self.robots.lock().unwrap().get(&origin) {
+            return rules.is_allowed(url.path());
+        }
+
+        // Fetched without holding the lock so other hosts are not blocked.
+        // Threads racing on the same host keep the rules stored first.
+        let rules = match self.fetch_robots_txt(&origin) {
+            Some(rules) => rules,
+            // Not cached, so it is fetched again on the next request
+            None => return false,
+        };
+        self.robots
+            .lock()
+            .unwrap()
+            .entry(origin)
+            .or_insert(rules)
+            .is_allowed(url.path())
+    }
+
+    /// Fetch `robots.txt` of `origin`
+    ///
+    /// Everything is allowed when there is no `robots.txt`. `None` is
+    /// returned when the server is unreachable or fails to return it, in
+    /// which case the request is disallowed.
+    fn fetch_robots_txt(&self, origin: &str) -> Option<RobotsTxt> {
+        let robots_url = match Url::parse(&format!("{}/robots.txt", origin)) {
+            Ok(robots_url) => robots_url,
+            Err(_) => return Some(RobotsTxt::default()),
+        };
+        let mut response = match self.send(&robots_url, |client| client.get(robots_url.clone())) {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(
+                    "Failed to fetch robots.txt of {} ({}), disallowing the request",
+                    origin, err
+                );
+                return None;
+            }
+        };
+
+        if response.status().is_server_error() {
+            warn!(
+                "Failed to fetch robots.txt of {} ({}), disallowing the request",
+                origin,
+                response.status()
+            );
+            return None;
+        }
+
+        match response.text() {
+            Ok(content) if response.status().is_success() => {
+                info!("Using robots.txt of {}", origin);
+                Some(RobotsTxt::parse(&content, &self.config.user_agent))
+            }
+            _ => Some(RobotsTxt::default()),
+        }
+    }
+
+    /// Send request built by `build`, retrying on connection errors, timeouts
+    /// and responses which indicate a temporary failure
+    ///
+    /// The response of the last attempt is returned even if it is not
+    /// successful, so callers can inspect its status.
+    fn send<F>(&self, url: &Url, build: F) -> Result<Response, reqwest::Error>
+    where
+        F: Fn(&reqwest::Client) -> RequestBuilder,
+    {
+        let host = url.host_str().unwrap_or("");
+        let mut attempt = 0;
+        loop {
+            self.rate_limiter.acquire(host);
+            let outcome = build(&self.client).send();
+            let retryable = match &outcome {
+                Ok(response) => is_retryable_status(response.status()),
+                Err(err) => err.is_timeout() || err.is_http(),
+            };
+
+            if !retryable || attempt >= self.config.retries {
+                return outcome;
+            }
+
+            let wait = self.backoff(attempt);
+            match &outcome {
+                Ok(response) => warn!(
+                    "Request to {} returned {}, retrying in {:?}",
+                    response.url(),
+                    response.status(),
+                    wait
+                ),
+                Err(err) => warn!("Request failed: {}, retrying in {:?}", err, wait),
+            }
+            thread::sleep(wait);
+            attempt += 1;
+        }
+    }
+
+    /// Wait before the retry following `attempt`, doubling `backoff` on each
+    /// attempt up to `max_backoff`
+    fn backoff(&self, attempt: u32) -> Duration {
+        2u32.checked_pow(attempt)
+            .and_then(|factor| self.config.backoff.checked_mul(factor))
+            .map_or(self.config.max_backoff, |wait| {
+                wait.min(self.config.max_backoff)
+            })
+    }
+}
+
+/// Check if given status indicates a temporary failure
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serve `count` requests on a local port, answering with `respond` for
+    /// each request line, and return the base url and the received requests
+    pub(crate) fn serve<F>(count: usize, respond: F) -> (String, thread::JoinHandle<Vec<String>>)
+    where
+        F: Fn(&str) -> (u16, Vec<u8>) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(count) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    let lower = header.to_lowercase();
+                    if let Some(value) = lower.strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, content) = respond(&request_line);
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    content.len()
+                )
+                .unwrap();
+                stream.write_all(&content).unwrap();
+                requests.push(format!(
+                    "{}{}",
+                    request_line,
+                    String::from_utf8_lossy(&body)
+                ));
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    fn retrying_config() -> HttpConfig {
+        HttpConfig {
+            retries: 3,
+            backoff: Duration::from_millis(1),
+            requests_per_second: 0.0,
+            ..HttpConfig::default()
+        }
+    }
+
+    #[test]
+    fn backoff_is_doubled_up_to_the_cap() {
+        let http = HttpClient::new(HttpConfig {
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            ..HttpConfig::default()
+        })
+        .unwrap();
+        let schedule = (0..5)
+            .map(|attempt| http.backoff(attempt))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            schedule,
+            vec![100, 200, 400, 800, 1000]
+                .into_iter()
+                .map(Duration::from_millis)
+                .collect::<Vec<_>>()
+        );
+        // Would overflow without the cap
+        assert_eq!(http.backoff(40), Duration::from_secs(1));
+        assert_eq!(http.backoff(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn temporary_failures_are_retried() {
+        let failures = AtomicUsize::new(0);
+        let (url, handle) = serve(4, move |request_line| {
+            if request_line.starts_with("GET /robots.txt") {
+                (404, Vec::new())
+            } else if failures.fetch_add(1, Ordering::SeqCst) < 2 {
+                (503, Vec::new())
+            } else {
+                (200, b"ok".to_vec())
+            }
+        });
+        let http = HttpClient::new(retrying_config()).unwrap();
+
+        let mut response = http.get(&format!("{}/page", url)).unwrap();
+        assert_eq!(response.text().unwrap(), "ok");
+
+        let requests = handle.join().unwrap();
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|r| r.starts_with("GET /page"))
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn robots_txt_server_error_disallows_everything() {
+        let (url, handle) = serve(1, |_request_line| (503, Vec::new()));
+        let http = HttpClient::new(HttpConfig {
+            retries: 0,
+            ..retrying_config()
+        })
+        .unwrap();
+
+        match http.get(&format!("{}/page", url)) {
+            Err(ButterflyError::DisallowedByRobotsTxt(_)) => (),
+            other => panic!("expected DisallowedByRobotsTxt, got {:?}", other),
+        }
+        assert!(handle.join().unwrap()[0].starts_with("GET /robots.txt"));
+
+        // Nothing listens on the discard port
+        assert!(matches!(
+            http.get("http://127.0.0.1:9/page"),
+            Err(ButterflyError::DisallowedByRobotsTxt(_))
+        ));
+    }
+
+    #[test]
+    fn robots_txt_is_fetched_again_after_server_error() {
+        let robots_fetches = AtomicUsize::new(0);
+        let (url, handle) = serve(3, move |request_line| {
+            if !request_line.starts_with("GET /robots.txt") {
+                (200, b"page".to_vec())
+            } else if robots_fetches.fetch_add(1, Ordering::SeqCst) == 0 {
+                (503, Vec::new())
+            } else {
+                (200, b"User-agent: *\nDisallow: /private".to_vec())
+            }
+        });
+        let http = HttpClient::new(HttpConfig {
+            retries: 0,
+            ..retrying_config()
+        })
+        .unwrap();
+
+        let page = format!("{}/page", url);
+        assert!(http.get(&page).is_err());
+        assert_eq!(http.get(&page).unwrap().text().unwrap(), "page");
+        let requests = handle.join().unwrap();
+        assert!(requests[1].starts_with("GET /robots.txt"));
+    }
+}
@@ -0,0 +1,305 @@
+//! # Image Annotations
+//!
+//! This module exports the annotations, other than the dominant colors, that
+//! Google Cloud Vision API can return for an image of a butterfly. They are
+//! requested with `CloudVisionConfig::features` and stored on
+//! `Butterfly::annotations`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Words in labels or object names which indicate a butterfly
+const BUTTERFLY_WORDS: [&str; 3] = ["butterfly", "moth", "lepidoptera"];
+
+/// Optional feature of Google Cloud Vision API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VisionFeature {
+    /// Labels describing the image
+    LabelDetection,
+    /// Suggested crop regions of the image
+    CropHints,
+    /// Objects found in the image and their location
+    ObjectLocalization,
+    /// Likelihood of unsafe content
+    SafeSearchDetection,
+}
+
+impl VisionFeature {
+    /// Name of the feature type used by the API
+    pub(crate) fn api_name(self) -> &'static str {
+        match self {
+            VisionFeature::LabelDetection => "LABEL_DETECTION",
+            VisionFeature::CropHints => "CROP_HINTS",
+            VisionFeature::ObjectLocalization => "OBJECT_LOCALIZATION",
+            VisionFeature::SafeSearchDetection => "SAFE_SEARCH_DETECTION",
+        }
+    }
+}
+
+/// Label describing an image
+#[derive(Debug, PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct Label {
+    /// Description such as `Butterfly`
+    pub description: String,
+    /// Confidence
+    #[serde(default)]
+    pub score: f32,
+    /// Relevancy of the label to the image
+    #[serde(default)]
+    pub topicality: f32,
+}
+
+/// Point of an image in pixels
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Vertex {
+    /// X coordinate
+    #[serde(default)]
+    pub x: u32,
+    /// Y coordinate
+    #[serde(default)]
+    pub y: u32,
+}
+
+/// Point of an image relative to its size, from 0 to 1
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NormalizedVertex {
+    /// X coordinate
+    #[serde(default)]
+    pub x: f32,
+    /// Y coordinate
+    #[serde(default)]
+    pub y: f32,
+}
+
+/// Suggested crop region of an image
+#[derive(Debug, PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct CropHint {
+    /// Vertices of the region
+    pub vertices: Vec<Vertex>,
+    /// Confidence
+    pub confidence: f32,
+    /// Fraction of the image covered by the region
+    pub importance_fraction: f32,
+}
+
+impl CropHint {
+    /// Bounding box of the region as `(x, y, width, height)`
+    pub fn bounding_box(&self) -> Option<(u32, u32, u32, u32)> {
+        let min_x = self.vertices.iter().map(|v| v.x).min()?;
+        let min_y = self.vertices.iter().map(|v| v.y).min()?;
+        let max_x = self.vertices.iter().map(|v| v.x).max()?;
+        let max_y = self.vertices.iter().map(|v| v.y).max()?;
+        Some((min_x, min_y, max_x - min_x, max_y - min_y))
+    }
+}
+
+/// Object found in an image
+#[derive(Debug, PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct LocalizedObject {
+    /// Name such as `Butterfly`
+    pub name: String,
+    /// Confidence
+    pub score: f32,
+    /// Vertices of the region of the object
+    pub vertices: Vec<NormalizedVertex>,
+}
+
+/// Likelihood of unsafe content, such as `VERY_UNLIKELY`
+#[derive(Debug, PartialEq, PartialOrd, Clone, Default, Serialize, Deserialize)]
+pub struct SafeSearch {
+    /// Adult content
+    #[serde(default)]
+    pub adult: String,
+    /// Modified version of a well known image
+    #[serde(default)]
+    pub spoof: String,
+    /// Medical content
+    #[serde(default)]
+    pub medical: String,
+    /// Violent content
+    #[serde(default)]
+    pub violence: String,
+    /// Racy content
+    #[serde(default)]
+    pub racy: String,
+}
+
+/// Annotations of an image other than its dominant colors
+#[derive(Debug, PartialEq, PartialOrd, Clone, Default, Serialize, Deserialize)]
+pub struct ImageAnnotations {
+    /// Labels, most relevant first
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    /// Suggested crop regions
+    #[serde(default)]
+    pub crop_hints: Vec<CropHint>,
+    /// Objects found in the image
+    #[serde(default)]
+    pub objects: Vec<LocalizedObject>,
+    /// Likelihood of unsafe content
+    #[serde(default)]
+    pub safe_search: Option<SafeSearch>,
+}
+
+impl ImageAnnotations {
+    /// Check if any label or object indicates a butterfly
+    ///
+    /// Returns `None` when neither labels nor objects were returned.
+    pub fn contains_butterfly(&self) -> Option<bool> {
+        if self.labels.is_empty() && self.objects.is_empty() {
+            return None;
+        }
+
+        let names = self
+            .labels
+            .iter()
+            .map(|l| &l.description)
+            .chain(self.objects.iter().map(|o| &o.name));
+
+        Some(
+            names
+                .map(|name| name.to_lowercase())
+                .any(|name| BUTTERFLY_WORDS.iter().any(|word| name.contains(word))),
+        )
+    }
+}
+
+/// Bounding polygon in the response of the API
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBoundingPoly {
+    #[serde(default)]
+    vertices: Vec<Vertex>,
+    #[serde(default)]
+    normalized_vertices: Vec<NormalizedVertex>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCropHint {
+    #[serde(default)]
+    bounding_poly: RawBoundingPoly,
+    #[serde(default)]
+    confidence: f32,
+    #[serde(default)]
+    importance_fraction: f32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCropHints {
+    #[serde(default)]
+    crop_hints: Vec<RawCropHint>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawObject {
+    name: String,
+    #[serde(default)]
+    score: f32,
+    #[serde(default)]
+    bounding_poly: RawBoundingPoly,
+}
+
+/// Extract `ImageAnnotations` from the response of an image
+///
+/// Annotations which could not be parsed are left empty.
+pub(crate) fn extract_annotations(image_response: &Value) -> ImageAnnotations {
+    fn parse<T: serde::de::DeserializeOwned + Default>(value: &Value) -> T {
+        serde_json::from_value(value.to_owned()).unwrap_or_default()
+    }
+
+    let labels: Vec<Label> = parse(&image_response["labelAnnotations"]);
+
+    let crop_hints = parse::<RawCropHints>(&image_response["cropHintsAnnotation"])
+        .crop_hints
+        .into_iter()
+        .map(|hint| CropHint {
+            vertices: hint.bounding_poly.vertices,
+            confidence: hint.confidence,
+            importance_fraction: hint.importance_fraction,
+        })
+        .collect();
+
+    let objects = parse::<Vec<RawObject>>(&image_response["localizedObjectAnnotations"])
+        .into_iter()
+        .map(|object| LocalizedObject {
+            name: object.name,
+            score: object.score,
+            vertices: object.bounding_poly.normalized_vertices,
+        })
+        .collect();
+
+    let safe_search = match &image_response["safeSearchAnnotation"] {
+        Value::Null => None,
+        value => serde_json::from_value(value.to_owned()).ok(),
+    };
+
+    ImageAnnotations {
+        labels,
+        crop_hints,
+        objects,
+        safe_search,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn annotations_are_extracted_from_response() {
+        let response = json!({
+            "labelAnnotations": [
+                {"mid": "/m/0cyf8", "description": "Butterfly", "score": 0.97, "topicality": 0.97},
+                {"mid": "/m/03vt0", "description": "Insect", "score": 0.93, "topicality": 0.93}
+            ],
+            "cropHintsAnnotation": {
+                "cropHints": [{
+                    "boundingPoly": {"vertices": [{"x": 10}, {"x": 110}, {"x": 110, "y": 80}, {"x": 10, "y": 80}]},
+                    "confidence": 0.8,
+                    "importanceFraction": 1.0
+                }]
+            },
+            "localizedObjectAnnotations": [{
+                "mid": "/m/0cyf8",
+                "name": "Butterfly",
+                "score": 0.9,
+                "boundingPoly": {"normalizedVertices": [{"x": 0.1, "y": 0.2}, {"x": 0.9, "y": 0.8}]}
+            }],
+            "safeSearchAnnotation": {
+                "adult": "VERY_UNLIKELY", "spoof": "UNLIKELY", "medical": "VERY_UNLIKELY",
+                "violence": "VERY_UNLIKELY", "racy": "VERY_UNLIKELY"
+            }
+        });
+
+        let annotations = extract_annotations(&response);
+
+        assert_eq!(annotations.labels.len(), 2);
+        assert_eq!(
+            annotations.crop_hints[0].bounding_box(),
+            Some((10, 0, 100, 80))
+        );
+        assert_eq!(annotations.objects[0].vertices[1].x, 0.9);
+        assert_eq!(annotations.safe_search.unwrap().spoof, "UNLIKELY");
+        assert_eq!(
+            extract_annotations(&response).contains_butterfly(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn butterfly_check_needs_labels_or_objects() {
+        let mut annotations = ImageAnnotations::default();
+        assert_eq!(annotations.contains_butterfly(), None);
+
+        annotations.labels.push(Label {
+            description: "Flower".to_string(),
+            score: 0.9,
+            topicality: 0.9,
+        });
+        assert_eq!(annotations.contains_butterfly(), Some(false));
+    }
+}
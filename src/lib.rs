@@ -11,9 +11,15 @@
 //!
 //! ### Extracting data from website
 //!
-//! Start using this library by defining an instance of `Client` which takes
-//! vector of `WebpageParser`.
-//!  
+//! Start using this library by defining an instance of `Client`. The region
+//! pages to extract data from are listed in `regions.toml`, which is loaded
+//! with `from_config`.
+//!
+//! ```rust
+//!let mut client = Client::from_config("./regions.toml").unwrap();
+//! ```
+//!
+//! You can also give vector of `WebpageParser` to `new`.
 //!
 //! ```rust
 //!let mut client = Client::new(vec![
@@ -22,29 +28,80 @@
 //!        "旧北区",
 //!        "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall",
 //!    ),
+//!]);
+//! ```
+//!
+//! Region pages can also be discovered from the index page of the website.
+//! Regions which are not in the catalogue are added, and a warning is emitted
+//! for every region that is no longer linked.
+//!
+//! ```rust
+//!let mut client = Client::from_config("./regions.toml")
+//!    .unwrap()
+//!    .with_discovered_regions(&PageSource::Http(INDEX_URL.to_string()))
+//!    .unwrap();
+//! ```
+//!
+//! ### Correcting irregular names
+//!
+//! A few cells on the website do not follow the usual layout. Corrections for
+//! them are listed in `name_overrides.json`, which is bundled into the crate
+//! and used by default. More corrections can be given to `Client` with
+//! `with_name_overrides`. Use `name_override_report` after collecting the data
+//! to find overrides which no longer match anything.
+//!
+//! ```rust
+//!let overrides = NameOverrides::from_path("./my_name_overrides.json").unwrap();
+//!let mut client = Client::new(targets).with_name_overrides(overrides);
+//! ```
+//!
+//! ### Skipping bad cells
+//!
+//! By default a region is lost as soon as a single cell cannot be parsed. Use
+//! lenient `ParseOptions` to skip such cells instead; each of them is recorded
+//! in `Client::parse_report` and the region only fails when more than the
+//! given number of cells are bad.
+//!
+//! ```rust
+//!let mut client = Client::new(targets).with_parse_options(ParseOptions::lenient(10));
+//! ```
+//!
+//! ### Parsing pages saved on the disk
+//!
+//! Region pages can also be read from snapshots instead of the website with
+//! `PageSource`, which is useful to re-parse archived pages without network.
+//!
+//! ```rust
+//!let mut client = Client::new(vec![
 //!    WebpageParser::new(
-//!        "new_north",
-//!        "新北区",
-//!        "http://biokite.com/worldbutterfly/butterfly-NArc.htm#NAsa",
-//!    ),
-//!    WebpageParser::new(
-//!        "new_tropical",
-//!        "新熱帯区",
-//!        "http://biokite.com/worldbutterfly/butterfly-NTro.htm#NTmap",
-//!    ),
-//!    WebpageParser::new(
-//!        "india_australia",
-//!        "インド・オーストラリア区",
-//!        "http://biokite.com/worldbutterfly/butterfly-IOrs.htm#IOmap",
-//!    ),
-//!    WebpageParser::new(
-//!        "tropical_africa",
-//1        "熱帯アフリカ区",
-//!        "http://biokite.com/worldbutterfly/butterfly-TAfr.htm#TAmaps",
-//!    ),
+//!        "old_north",
+//!        "旧北区",
+//!        "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall",
+//!    )
+//!    .with_source(PageSource::file("./snapshots/old_north.htm")),
 //!]);
 //! ```
 //!
+//! ### Configuring http requests
+//!
+//! Every request is sent through `HttpClient`, which times out and retries
+//! transient failures with exponential backoff. Requests are rate limited per
+//! host and pages disallowed by `robots.txt` are skipped, as the website is a
+//! small hobbyist site. Give your own to `Client` to change the policy; it is
+//! also used by the returned `ButterflyCollector`.
+//!
+//! ```rust
+//!let http = HttpClient::new(HttpConfig {
+//!    timeout: Duration::from_secs(10),
+//!    retries: 5,
+//!    ..HttpConfig::default()
+//!})
+//!.unwrap();
+//!let mut client = Client::from_config("./regions.toml")
+//!    .unwrap()
+//!    .with_http_client(http);
+//! ```
+//!
 //! ### Acquiring data via JSON file
 //!
 //! If you have done the whole data extraction process before and want to use
@@ -71,6 +128,11 @@
 //!    .unwrap();
 //! ```
 //!
+//! Downloads can be resumed. Files already stored whose SHA-256 matches the one
+//! recorded in the JSON file (`img_sha256`, `pdf_sha256`) are not fetched again,
+//! so running the same steps on the collector returned by `Client::from_path`
+//! only downloads what is missing or has changed.
+//!
 //! After everything is done, call `store_json` to store the result as json file
 //!
 //! ```rust
@@ -84,8 +146,10 @@
 
 extern crate anyhow;
 extern crate csv;
+extern crate encoding_rs;
 extern crate env_logger;
 extern crate hex;
+extern crate image;
 extern crate kana;
 extern crate kanaria;
 extern crate log;
@@ -95,20 +159,59 @@ extern crate scoped_threadpool;
 extern crate scraper;
 extern crate serde;
 extern crate serde_json;
+extern crate sha2;
 extern crate thiserror;
+extern crate toml;
 
 mod butterfly;
 mod butterfly_collector;
+mod butterfly_id;
 mod client;
 mod cloud_vision;
+mod color_extractor;
+mod color_names;
+mod color_space;
 mod constants;
 mod csv_data;
+mod csv_report;
+mod download;
 mod errors;
+mod http_client;
+mod image_annotations;
+mod name_overrides;
+mod page_source;
+mod politeness;
+mod region_config;
+mod region_discovery;
+mod scientific_name;
+mod search_index;
+mod species;
+mod taxonomy;
 mod webpage_parser;
 
 pub use butterfly::Butterfly;
 pub use butterfly_collector::ButterflyCollector;
-pub use client::Client;
-pub use cloud_vision::Color;
+pub use butterfly_id::{ButterflyDiff, ButterflyId};
+pub use client::{Client, RegionError};
+pub use cloud_vision::{ApiKeySource, CloudVisionConfig, Color, ImageMode};
+pub use color_extractor::{CloudVision, ColorExtractor, LocalColorExtractor, Palette};
+pub use color_names::{ColorFamily, ColorName, ColorNames};
+pub use color_space::Lab;
+pub use constants::INDEX_URL;
+pub use csv_data::{CsvColumns, CsvConfig};
+pub use csv_report::{CsvIssue, CsvIssueKind, CsvReport};
 pub use errors::ButterflyError;
-pub use webpage_parser::WebpageParser;
+pub use http_client::{HttpClient, HttpConfig};
+pub use image_annotations::{
+    CropHint, ImageAnnotations, Label, LocalizedObject, NormalizedVertex, SafeSearch, Vertex,
+    VisionFeature,
+};
+pub use name_overrides::{MatchOn, NameOverride, NameOverrideReport, NameOverrides};
+pub use page_source::PageSource;
+pub use region_config::{RegionCatalogue, RegionConfig};
+pub use region_discovery::{discover_regions, DiscoveredRegion};
+pub use scientific_name::{ScientificName, Sex};
+pub use search_index::{SearchField, SearchHit, SearchIndex};
+pub use species::{Occurrence, Species};
+pub use taxonomy::{latin_family_name, FamilyNode, GenusNode, SpeciesNode, Taxonomy};
+pub use webpage_parser::{ParseIssue, ParseOptions, ParseReport, WebpageParser};
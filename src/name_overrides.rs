@@ -0,0 +1,167 @@
+//! # Name Overrides
+//!
+//! Some cells on the website do not follow the usual "Japanese name, newline,
+//! English name" layout. This module exports `NameOverrides`, a table of
+//! corrections keyed by the raw text of such cells. The corrections in
+//! `name_overrides.json` are bundled into the crate and used by default, and
+//! more can be loaded from a JSON file so new oddities can be handled without
+//! changing the parser.
+//!
+//! ```json
+//! [
+//!   {
+//!     "raw": "ヒメアカタテハCynthia_cardui",
+//!     "jp_name": "ヒメアカタテハ",
+//!     "eng_name": "Cynthia_cardui"
+//!   },
+//!   {
+//!     "raw": "ミイロタイマイ",
+//!     "match_on": "first_line",
+//!     "jp_name": "ミイロタイマイ",
+//!     "eng_name": "Graphium_weiskei"
+//!   }
+//! ]
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::errors::ButterflyError::{self, *};
+
+// Overrides bundled into the crate
+const BUNDLED_OVERRIDES: &str = include_str!("../name_overrides.json");
+
+/// Which part of the cell text is compared against `NameOverride::raw`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchOn {
+    /// Whole text of the cell
+    #[default]
+    Text,
+    /// First line of the cell
+    FirstLine,
+}
+
+/// Correction of the names extracted from a single cell
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NameOverride {
+    /// Raw text of the cell
+    pub raw: String,
+    /// Part of the cell which is compared against `raw`
+    #[serde(default)]
+    pub match_on: MatchOn,
+    /// Japanese name to use
+    pub jp_name: String,
+    /// English name to use
+    pub eng_name: String,
+}
+
+impl NameOverride {
+    /// Check if given cell text is the target of this override
+    fn matches(&self, text: &str) -> bool {
+        match self.match_on {
+            MatchOn::Text => text.trim() == self.raw,
+            MatchOn::FirstLine => text.lines().next() == Some(self.raw.as_str()),
+        }
+    }
+}
+
+impl FromStr for NameOverrides {
+    type Err = ButterflyError;
+
+    /// Load overrides from JSON text
+    fn from_str(json: &str) -> Result<NameOverrides, ButterflyError> {
+        let overrides: Vec<NameOverride> = serde_json::from_str(json)
+            .map_err(|e| FailedToParseJson(format!("name overrides: {}", e)))?;
+
+        Ok(NameOverrides::new(overrides))
+    }
+}
+
+/// Table of `NameOverride` used by the parser
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameOverrides {
+    overrides: Vec<NameOverride>,
+}
+
+impl Default for NameOverrides {
+    /// Overrides bundled into the crate
+    fn default() -> Self {
+        BUNDLED_OVERRIDES
+            .parse()
+            .expect("bundled name_overrides.json is valid")
+    }
+}
+
+impl NameOverrides {
+    /// Create an new instance of `NameOverrides`
+    pub fn new(overrides: Vec<NameOverride>) -> NameOverrides {
+        NameOverrides { overrides }
+    }
+
+    /// Load overrides from JSON file
+    ///
+    /// ```rust
+    /// let overrides = NameOverrides::from_path("./name_overrides.json").unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<NameOverrides, ButterflyError> {
+        let path = path.as_ref().display().to_string();
+        let file = File::open(&path).map_err(|_e| JsonFileNotFound(path.clone()))?;
+        let overrides: Vec<NameOverride> =
+            serde_json::from_reader(BufReader::new(file)).map_err(|_e| FailedToParseJson(path))?;
+
+        Ok(NameOverrides::new(overrides))
+    }
+
+    /// Put `overrides` on top of these, so that they are tried first
+    ///
+    /// Overrides of the same cell text are replaced.
+    pub fn layer(&mut self, overrides: NameOverrides) {
+        self.overrides.retain(|existing| {
+            !overrides
+                .overrides
+                .iter()
+                .any(|o| o.raw == existing.raw && o.match_on == existing.match_on)
+        });
+        let mut layered = overrides.overrides;
+        layered.append(&mut self.overrides);
+        self.overrides = layered;
+    }
+
+    /// Find the override which applies to given cell text
+    pub(crate) fn find(&self, text: &str) -> Option<&NameOverride> {
+        self.overrides.iter().find(|o| o.matches(text))
+    }
+
+    /// Build a report of which overrides were applied, given the number of
+    /// times each `raw` text has been used
+    pub fn report(&self, usage: &HashMap<String, usize>) -> NameOverrideReport {
+        let mut used = Vec::new();
+        let mut unused = Vec::new();
+
+        for name_override in self.overrides.iter() {
+            match usage.get(&name_override.raw) {
+                Some(count) => used.push((name_override.raw.to_owned(), *count)),
+                None => unused.push(name_override.raw.to_owned()),
+            }
+        }
+
+        NameOverrideReport { used, unused }
+    }
+}
+
+/// Report of which `NameOverride` were used when parsing pages
+///
+/// Overrides listed in `unused` no longer match anything on the website and
+/// can be pruned.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameOverrideReport {
+    /// Raw text of the overrides that were applied, with the number of times
+    pub used: Vec<(String, usize)>,
+    /// Raw text of the overrides that were never applied
+    pub unused: Vec<String>,
+}
@@ -0,0 +1,59 @@
+//! # Page Source
+//!
+//! This module exports `PageSource` which describes where the html of a
+//! region page comes from. Pages can be fetched from the website, read from
+//! a snapshot saved on disk, or given directly as a string.
+
+use encoding_rs::SHIFT_JIS;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::errors::ButterflyError;
+use super::http_client::HttpClient;
+
+// Encoding used on the butterfly website
+const WEBSITE_CHARSET: &str = "Shift-JIS";
+
+/// Source of the html of a region page
+#[derive(Debug, Clone, Hash, PartialEq, PartialOrd, Ord, Eq)]
+pub enum PageSource {
+    /// Fetch the page from given url
+    Http(String),
+    /// Read the page saved on the disk, encoded in Shift-JIS like the website
+    File(PathBuf),
+    /// Use given html as is
+    Text(String),
+}
+
+impl PageSource {
+    /// Create `PageSource` which reads the page saved on `path`
+    ///
+    /// ```rust
+    /// PageSource::file("./snapshots/old_north.htm")
+    /// ```
+    pub fn file<P: AsRef<Path>>(path: P) -> PageSource {
+        PageSource::File(path.as_ref().to_path_buf())
+    }
+
+    /// Read the html from the source, using `http` to fetch it if needed
+    pub(crate) fn read(&self, http: &HttpClient) -> Result<String, ButterflyError> {
+        match self {
+            PageSource::Http(url) => {
+                request_html(http, url).map_err(|_e| ButterflyError::FailedToFetchHTML(url.clone()))
+            }
+            PageSource::File(path) => {
+                let bytes = fs::read(path)
+                    .map_err(|_e| ButterflyError::FileNotFound(path.display().to_string()))?;
+                let (html, _encoding, _had_errors) = SHIFT_JIS.decode(&bytes);
+                Ok(html.into_owned())
+            }
+            PageSource::Text(html) => Ok(html.to_owned()),
+        }
+    }
+}
+
+///Fetch content of given `url`
+fn request_html(http: &HttpClient, url: &str) -> anyhow::Result<String> {
+    let mut req = http.get(url)?.error_for_status()?;
+    Ok(req.text_with_charset(WEBSITE_CHARSET)?)
+}
@@ -0,0 +1,237 @@
+//! # Politeness
+//!
+//! biokite.com is a small hobbyist site. This module exports `RateLimiter`,
+//! a per-host token bucket, and `RobotsTxt`, the rules of a host's
+//! `robots.txt`, which `HttpClient` uses so that crawling stays polite.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket of a single host
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Per-host token bucket rate limiter
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    requests_per_second: f64,
+    burst: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Create an new instance of `RateLimiter` which allows `burst` requests
+    /// at once and `requests_per_second` on average for each host
+    pub(crate) fn new(requests_per_second: f64, burst: u32) -> RateLimiter {
+        RateLimiter {
+            requests_per_second,
+            burst: burst.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Block until a request to `host` is allowed
+    pub(crate) fn acquire(&self, host: &str) {
+        if self.requests_per_second <= 0.0 {
+            return;
+        }
+
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let burst = f64::from(self.burst);
+                let bucket = buckets.entry(host.to_string()).or_insert(Bucket {
+                    tokens: burst,
+                    last_refill: Instant::now(),
+                });
+
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(burst);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                (1.0 - bucket.tokens) / self.requests_per_second
+            };
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+/// Single `Allow` or `Disallow` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    path: String,
+}
+
+/// Rules of `robots.txt` which apply to this crate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RobotsTxt {
+    rules: Vec<Rule>,
+}
+
+impl RobotsTxt {
+    /// Parse `robots.txt`, keeping the group for `user_agent` if there is one
+    /// and the group for `*` otherwise
+    pub(crate) fn parse(content: &str, user_agent: &str) -> RobotsTxt {
+        // Product token, e.g. "butterfly_extractor" of "butterfly_extractor/0.1.0"
+        let product = user_agent
+            .split(|c: char| c == '/' || c.is_whitespace())
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+
+        let mut specific: Vec<Rule> = Vec::new();
+        let mut wildcard: Vec<Rule> = Vec::new();
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.find(':') {
+                Some(i) => (line[..i].trim().to_lowercase(), line[i + 1..].trim()),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    // A new group starts after the rules of previous one
+                    if in_rules {
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_lowercase());
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // Empty Disallow allows everything
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let rule = Rule {
+                        allow: key == "allow",
+                        path: value.to_string(),
+                    };
+                    if !product.is_empty() && agents.contains(&product) {
+                        specific.push(rule.clone());
+                    }
+                    if agents.iter().any(|a| a == "*") {
+                        wildcard.push(rule);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let rules = if specific.is_empty() {
+            wildcard
+        } else {
+            specific
+        };
+        RobotsTxt { rules }
+    }
+
+    /// Check if `path` may be fetched
+    ///
+    /// The longest matching rule wins, and `Allow` wins over `Disallow` of the
+    /// same length.
+    pub(crate) fn is_allowed(&self, path: &str) -> bool {
+        let mut best: Option<&Rule> = None;
+        for rule in self.rules.iter().filter(|r| path_matches(&r.path, path)) {
+            best = match best {
+                Some(b) if b.path.len() > rule.path.len() => Some(b),
+                Some(b) if b.path.len() == rule.path.len() && b.allow => Some(b),
+                _ => Some(rule),
+            };
+        }
+        best.map(|rule| rule.allow).unwrap_or(true)
+    }
+}
+
+/// Check if `path` matches `pattern`, which may contain `*` and end with `$`
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let parts = pattern.split('*').collect::<Vec<&str>>();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return if anchored {
+            path == pattern
+        } else {
+            path.starts_with(pattern)
+        };
+    }
+    if !path.starts_with(first) {
+        return false;
+    }
+
+    let mut position = first.len();
+    for part in parts[1..parts.len() - 1].iter() {
+        match path[position..].find(part) {
+            Some(i) => position += i + part.len(),
+            None => return false,
+        }
+    }
+
+    if anchored {
+        path.len() >= position + last.len() && path.ends_with(last)
+    } else {
+        path[position..].contains(last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS_TXT: &str = "
+        # Comments are ignored
+        User-agent: *
+        Disallow: /cgi-bin/
+        Disallow: /worldbutterfly/*.pdf$
+        Allow: /worldbutterfly/butterfly_pdf/free.pdf
+
+        User-agent: BadBot
+        Disallow: /
+    ";
+
+    #[test]
+    fn wildcard_group_applies() {
+        let robots = RobotsTxt::parse(ROBOTS_TXT, "butterfly_extractor/0.1.0");
+
+        assert!(robots.is_allowed("/worldbutterfly/butterfly-PArc.htm"));
+        assert!(!robots.is_allowed("/cgi-bin/counter"));
+        assert!(!robots.is_allowed("/worldbutterfly/butterfly_pdf/i_o/i_o-am_1.pdf"));
+        assert!(robots.is_allowed("/worldbutterfly/butterfly_pdf/free.pdf"));
+    }
+
+    #[test]
+    fn specific_group_overrides_wildcard() {
+        let robots = RobotsTxt::parse(ROBOTS_TXT, "BadBot/2.0");
+
+        assert!(!robots.is_allowed("/worldbutterfly/butterfly-PArc.htm"));
+    }
+
+    #[test]
+    fn rate_limiter_allows_burst_then_waits() {
+        let limiter = RateLimiter::new(20.0, 2);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("biokite.com");
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,138 @@
+//! # Region Config
+//!
+//! This module exports `RegionConfig` which describes the region pages to
+//! extract data from. The catalogue is loaded from a TOML file so regions can
+//! be added, disabled or pointed at mirrors without recompiling.
+//!
+//! ```toml
+//! [[regions]]
+//! dir_name = "old_north"
+//! name = "旧北区"
+//! eng_name = "Palearctic"
+//! url = "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall"
+//!
+//! [[regions]]
+//! dir_name = "new_north"
+//! name = "新北区"
+//! url = "http://biokite.com/worldbutterfly/butterfly-NArc.htm#NAsa"
+//! enabled = false
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use super::errors::ButterflyError::{self, *};
+use super::webpage_parser::WebpageParser;
+
+/// Single region page listed in the catalogue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionConfig {
+    /// Directory name to store assets
+    pub dir_name: String,
+    /// Japanese name of the region
+    pub name: String,
+    /// Url of region page
+    pub url: String,
+    /// English name of the region
+    #[serde(default)]
+    pub eng_name: Option<String>,
+    /// Whether the region is extracted
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl RegionConfig {
+    /// Create `WebpageParser` for this region
+    pub fn to_parser(&self) -> WebpageParser {
+        let parser = WebpageParser::new(&self.dir_name, &self.name, &self.url);
+        match &self.eng_name {
+            Some(eng_name) => parser.with_eng_region(eng_name),
+            None => parser,
+        }
+    }
+}
+
+/// Catalogue of region pages
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionCatalogue {
+    /// List of regions
+    pub regions: Vec<RegionConfig>,
+}
+
+impl RegionCatalogue {
+    /// Load the catalogue from TOML file
+    ///
+    /// ```rust
+    /// let catalogue = RegionCatalogue::from_path("./regions.toml").unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<RegionCatalogue, ButterflyError> {
+        let path = path.as_ref().display().to_string();
+        let content = fs::read_to_string(&path).map_err(|_e| FileNotFound(path.clone()))?;
+        toml::from_str(&content).map_err(|e| FailedToParseConfig(path, e.to_string()))
+    }
+
+    /// Create `WebpageParser` for each of the enabled regions
+    pub fn parsers(&self) -> Vec<WebpageParser> {
+        self.regions
+            .iter()
+            .filter(|region| region.enabled)
+            .map(|region| region.to_parser())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_catalogue_lists_all_regions() {
+        let catalogue = RegionCatalogue::from_path("regions.toml").unwrap();
+        let dir_names = catalogue
+            .parsers()
+            .into_iter()
+            .map(|parser| parser.dir_name)
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            dir_names,
+            vec![
+                "old_north",
+                "new_north",
+                "new_tropical",
+                "india_australia",
+                "tropical_africa"
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_regions_are_skipped() {
+        let catalogue: RegionCatalogue = toml::from_str(
+            r#"
+            [[regions]]
+            dir_name = "old_north"
+            name = "旧北区"
+            eng_name = "Palearctic"
+            url = "http://mirror.example/butterfly-PArc.htm"
+
+            [[regions]]
+            dir_name = "new_north"
+            name = "新北区"
+            url = "http://biokite.com/worldbutterfly/butterfly-NArc.htm#NAsa"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        let parsers = catalogue.parsers();
+
+        assert_eq!(parsers.len(), 1);
+        assert_eq!(parsers[0].url, "http://mirror.example/butterfly-PArc.htm");
+        assert_eq!(parsers[0].eng_region, Some("Palearctic".to_string()));
+    }
+}
@@ -0,0 +1,165 @@
+//! # Region Discovery
+//!
+//! This module exports `discover_regions` which parses the index page of the
+//! website and finds every region page linked from it.
+
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use super::constants::*;
+use super::errors::ButterflyError;
+use super::http_client::HttpClient;
+use super::page_source::PageSource;
+use super::webpage_parser::WebpageParser;
+
+/// Region page linked from the index page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredRegion {
+    /// Text of the link, which is the Japanese name of the region
+    pub name: String,
+    /// Url of region page
+    pub url: String,
+}
+
+impl DiscoveredRegion {
+    /// Create `WebpageParser` for this region
+    ///
+    /// Directory name is derived from the file name of the page, e.g.
+    /// `butterfly-PArc.htm` is stored under `parc`.
+    pub fn to_parser(&self) -> WebpageParser {
+        let dir_name = page_stem(&self.url).unwrap_or_default().to_lowercase();
+        WebpageParser::new(&dir_name, &self.name, &self.url)
+    }
+
+    /// Check if this region is the one on `url` stored under `dir_name`
+    ///
+    /// Regions are matched by url, by directory name, and by the file name of
+    /// the page so that regions pointed at mirrors are recognized too.
+    pub(crate) fn is_page_of(&self, url: &str, dir_name: &str) -> bool {
+        if same_page(&self.url, url) || self.to_parser().dir_name == dir_name {
+            return true;
+        }
+        match (page_stem(&self.url), page_stem(url)) {
+            (Some(stem), Some(other)) => stem.eq_ignore_ascii_case(&other),
+            _ => false,
+        }
+    }
+}
+
+/// Find region pages linked from the index page read from `index`
+///
+/// Links are resolved against `INDEX_URL`. A region page linked several times
+/// with different anchors is listed once.
+pub fn discover_regions(
+    index: &PageSource,
+    http: &HttpClient,
+) -> Result<Vec<DiscoveredRegion>, ButterflyError> {
+    let html = index.read(http)?;
+    let fragment = Html::parse_document(&html);
+    let a_selector = Selector::parse("a").unwrap();
+    let base = Url::parse(INDEX_URL).unwrap();
+
+    let mut regions: Vec<DiscoveredRegion> = Vec::new();
+
+    for a in fragment.select(&a_selector) {
+        let url = match a.value().attr("href").and_then(|h| base.join(h).ok()) {
+            Some(url) => url,
+            None => continue,
+        };
+        if !is_region_page(&url) {
+            continue;
+        }
+
+        let name = a.text().collect::<String>().trim().to_string();
+        let url = url.to_string();
+        match regions.iter_mut().find(|r| same_page(&r.url, &url)) {
+            // Prefer links with text, as some of them are images
+            Some(region) if region.name.is_empty() => region.name = name,
+            Some(_) => (),
+            None => regions.push(DiscoveredRegion { name, url }),
+        }
+    }
+
+    Ok(regions)
+}
+
+/// Check if given urls point to the same page, ignoring the anchor
+pub(crate) fn same_page(url: &str, other: &str) -> bool {
+    let without_fragment = |u: &str| {
+        Url::parse(u).ok().map(|mut parsed| {
+            parsed.set_fragment(None);
+            parsed
+        })
+    };
+    match (without_fragment(url), without_fragment(other)) {
+        (Some(u), Some(o)) => u == o,
+        _ => url == other,
+    }
+}
+
+/// Check if given url is a region page on the website
+fn is_region_page(url: &Url) -> bool {
+    url.as_str().starts_with(BUTTERFLY_URL)
+        && match page_stem(url.as_str()) {
+            Some(stem) => !stem.is_empty() && stem != "index",
+            None => false,
+        }
+}
+
+/// Extract the part of file name between `butterfly-` and `.htm`
+fn page_stem(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let file_name = url.path_segments()?.next_back()?;
+    let stem = file_name.strip_prefix("butterfly-")?;
+    let stem = stem
+        .strip_suffix(".htm")
+        .or_else(|| stem.strip_suffix(".html"))?;
+    Some(stem.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEX_HTML: &str = r#"<html><body>
+        <a href="butterfly-PArc.htm#PAall"><img src="map.gif"></a>
+        <a href="butterfly-PArc.htm#PAall">旧北区</a>
+        <a href="butterfly-NArc.htm#NAsa">新北区</a>
+        <a href="http://biokite.com/worldbutterfly/butterfly-TAfr.htm#TAmaps"> 熱帯アフリカ区 </a>
+        <a href="butterfly-index.htm">目次</a>
+        <a href="butterfly_pdf/i_o/i_o-am_1.pdf">PDF</a>
+        <a href="http://example.com/butterfly-PArc.htm">mirror</a>
+        </body></html>"#;
+
+    #[test]
+    fn region_pages_are_discovered_once() {
+        let regions = discover_regions(
+            &PageSource::Text(INDEX_HTML.to_string()),
+            &HttpClient::default(),
+        )
+        .unwrap();
+        let found = regions
+            .iter()
+            .map(|r| (r.name.as_str(), r.url.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "旧北区",
+                    "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall"
+                ),
+                (
+                    "新北区",
+                    "http://biokite.com/worldbutterfly/butterfly-NArc.htm#NAsa"
+                ),
+                (
+                    "熱帯アフリカ区",
+                    "http://biokite.com/worldbutterfly/butterfly-TAfr.htm#TAmaps"
+                ),
+            ]
+        );
+        assert_eq!(regions[0].to_parser().dir_name, "parc");
+    }
+}
@@ -0,0 +1,193 @@
+//! # Scientific Name
+//!
+//! This module exports `ScientificName`, the parsed form of `eng_name` such as
+//! `Euploea_mulcibe`, and `Sex`, which is given by markers like `♀` or `♂`
+//! following the Japanese name of some butterflies.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::errors::ButterflyError::{self, *};
+
+// Markers of female and male
+const FEMALE_MARKER: char = '♀';
+const MALE_MARKER: char = '♂';
+
+/// Sex of the butterfly on the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sex {
+    /// Marked with `♀`
+    Female,
+    /// Marked with `♂`
+    Male,
+}
+
+impl Sex {
+    /// Find the sex marker in `text`
+    ///
+    /// Returns `None` when there is no marker or when both are present.
+    pub fn from_marker(text: &str) -> Option<Sex> {
+        match (text.contains(FEMALE_MARKER), text.contains(MALE_MARKER)) {
+            (true, false) => Some(Sex::Female),
+            (false, true) => Some(Sex::Male),
+            _ => None,
+        }
+    }
+
+    /// Marker of the sex, `♀` or `♂`
+    pub fn marker(self) -> char {
+        match self {
+            Sex::Female => FEMALE_MARKER,
+            Sex::Male => MALE_MARKER,
+        }
+    }
+}
+
+/// Scientific name in binomial or trinomial form
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ScientificName {
+    /// Genus such as `Euploea`
+    pub genus: String,
+    /// Specific epithet such as `mulcibe`
+    pub species: String,
+    /// Subspecific epithet if any
+    pub subspecies: Option<String>,
+}
+
+impl ScientificName {
+    /// Parse the scientific name from `eng_name`
+    ///
+    /// Words can be separated by `_` or spaces, and sex markers are ignored.
+    /// The genus must be capitalized, and the epithets in lower case.
+    ///
+    /// ```rust
+    /// let name = ScientificName::parse("Polygonia_c-aureum").unwrap();
+    /// assert_eq!(name.genus, "Polygonia");
+    /// assert_eq!(name.species, "c-aureum");
+    /// ```
+    pub fn parse(eng_name: &str) -> Result<ScientificName, ButterflyError> {
+        let invalid =
+            |reason: &str| InvalidScientificName(eng_name.to_string(), reason.to_string());
+
+        let words = eng_name
+            .split(|c: char| {
+                c == '_' || c.is_whitespace() || c == FEMALE_MARKER || c == MALE_MARKER
+            })
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+
+        let (genus, species, subspecies) = match words.as_slice() {
+            [genus, species] => (genus, species, None),
+            [genus, species, subspecies] => (genus, species, Some(subspecies)),
+            [] | [_] => return Err(invalid("expected genus and species")),
+            _ => return Err(invalid("too many words")),
+        };
+
+        if !is_genus(genus) {
+            return Err(invalid("genus must be a capitalized word"));
+        }
+        if !is_epithet(species) {
+            return Err(invalid("species must be a word in lower case"));
+        }
+        if let Some(subspecies) = subspecies {
+            if !is_epithet(subspecies) {
+                return Err(invalid("subspecies must be a word in lower case"));
+            }
+        }
+
+        Ok(ScientificName {
+            genus: genus.to_string(),
+            species: species.to_string(),
+            subspecies: subspecies.map(|s| s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for ScientificName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.genus, self.species)?;
+        if let Some(subspecies) = &self.subspecies {
+            write!(f, " {}", subspecies)?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove sex markers from `text`, such as `ツマムラサキマダラ♀`
+pub(crate) fn strip_sex_markers(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c != FEMALE_MARKER && *c != MALE_MARKER)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+fn is_genus(word: &str) -> bool {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.is_ascii_uppercase() && chars.all(|c| c.is_ascii_lowercase()),
+        None => false,
+    }
+}
+
+// Epithets may contain a hyphen, as in `c-aureum`
+fn is_epithet(word: &str) -> bool {
+    word.chars().any(|c| c.is_ascii_lowercase())
+        && word.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+        && !word.starts_with('-')
+        && !word.ends_with('-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scientific_names_are_parsed() {
+        let name = ScientificName::parse("Euploea_mulcibe").unwrap();
+        assert_eq!(name.genus, "Euploea");
+        assert_eq!(name.species, "mulcibe");
+        assert_eq!(name.subspecies, None);
+
+        let name = ScientificName::parse("Papilio machaon hippocrates").unwrap();
+        assert_eq!(name.subspecies, Some("hippocrates".to_string()));
+        assert_eq!(name.to_string(), "Papilio machaon hippocrates");
+
+        assert_eq!(
+            ScientificName::parse("Polygonia_c-aureum").unwrap().species,
+            "c-aureum"
+        );
+        assert!(ScientificName::parse("Graphium_weiskei♀").is_ok());
+    }
+
+    #[test]
+    fn names_not_in_binomial_form_are_rejected() {
+        for eng_name in [
+            "",
+            "Euploea",
+            "euploea_mulcibe",
+            "Euploea_Mulcibe",
+            "Euploea_sp.",
+            "Papilio_machaon_hippocrates_extra",
+            "ツマムラサキマダラ",
+        ]
+        .iter()
+        {
+            assert!(ScientificName::parse(eng_name).is_err(), "{}", eng_name);
+        }
+    }
+
+    #[test]
+    fn sex_is_found_from_marker() {
+        assert_eq!(Sex::from_marker("ツマムラサキマダラ♀"), Some(Sex::Female));
+        assert_eq!(Sex::from_marker("ツマムラサキマダラ♂"), Some(Sex::Male));
+        assert_eq!(Sex::from_marker("ツマムラサキマダラ"), None);
+        assert_eq!(Sex::from_marker("♀♂"), None);
+        assert_eq!(Sex::Female.marker(), '♀');
+        assert_eq!(
+            strip_sex_markers("ツマムラサキマダラ♀"),
+            "ツマムラサキマダラ"
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! # Search Index
+//!
+//! This module exports `SearchIndex`, an in-memory index used to search
+//! butterflies by their names, distribution and remarks.
+//!
+//! Both the indexed text and the query are normalized the same way as the
+//! data on the website: full-width alphanumerics and half-width kana are
+//! converted to their usual form, hiragana is converted to katakana and
+//! latin letters are lowercased. This means `あげは`, `アゲハ` and `ｱｹﾞﾊ` all
+//! find the same butterflies, as do `Papilio` and `ＰＡＰＩＬＩＯ`.
+
+use std::collections::{HashMap, HashSet};
+
+use super::butterfly::Butterfly;
+use super::csv_data;
+
+// Length of the n-grams stored in the index
+const NGRAM_SIZE: usize = 2;
+
+/// Field of `Butterfly` which is searched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchField {
+    /// Japanese name
+    JpName,
+    /// English name
+    EngName,
+    /// Distribution
+    Distribution,
+    /// Remarks
+    Remarks,
+}
+
+impl SearchField {
+    // Names weigh more than the description of a butterfly
+    fn weight(self) -> u32 {
+        match self {
+            SearchField::JpName | SearchField::EngName => 3,
+            SearchField::Distribution | SearchField::Remarks => 1,
+        }
+    }
+}
+
+/// Butterfly matching a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit<'a> {
+    /// Matching butterfly
+    pub butterfly: &'a Butterfly,
+    /// Relevance of the match, higher is better
+    pub score: u32,
+    /// Fields in which a term of the query was found
+    pub fields: Vec<SearchField>,
+}
+
+/// Normalized text of a butterfly
+#[derive(Debug)]
+struct Document {
+    fields: Vec<(SearchField, String)>,
+}
+
+/// In-memory index of butterflies
+///
+/// Each field is split into n-grams which are mapped to the butterflies
+/// containing them, so that a term can be found anywhere in a field.
+/// Matches at the start of a word rank higher than ones in the middle.
+#[derive(Debug)]
+pub struct SearchIndex<'a> {
+    butterflies: &'a [Butterfly],
+    documents: Vec<Document>,
+    ngrams: HashMap<String, HashSet<usize>>,
+}
+
+impl<'a> SearchIndex<'a> {
+    /// Create an new instance of `SearchIndex`
+    pub fn new(butterflies: &'a [Butterfly]) -> SearchIndex<'a> {
+        let mut documents = Vec::with_capacity(butterflies.len());
+        let mut ngrams: HashMap<String, HashSet<usize>> = HashMap::new();
+
+        for (id, butterfly) in butterflies.iter().enumerate() {
+            let mut fields = vec![
+                (SearchField::JpName, normalize(&butterfly.jp_name)),
+                (SearchField::EngName, normalize(&butterfly.eng_name)),
+                (
+                    SearchField::Distribution,
+                    normalize(&butterfly.distribution),
+                ),
+            ];
+            if let Some(remarks) = &butterfly.remarks {
+                fields.push((SearchField::Remarks, normalize(remarks)));
+            }
+
+            for (_, text) in fields.iter() {
+                for ngram in text_ngrams(text) {
+                    ngrams.entry(ngram).or_default().insert(id);
+                }
+            }
+            documents.push(Document { fields });
+        }
+
+        SearchIndex {
+            butterflies,
+            documents,
+            ngrams,
+        }
+    }
+
+    /// Search butterflies containing every term of `query`
+    ///
+    /// Terms are separated by whitespace. At most `k` butterflies are
+    /// returned, most relevant first.
+    ///
+    /// ```rust
+    /// for hit in butterfly_data.search_index().search("あげは 沖縄", 10) {
+    ///     println!("{} {}", hit.butterfly.jp_name, hit.score);
+    /// }
+    /// ```
+    pub fn search(&self, query: &str, k: usize) -> Vec<SearchHit<'a>> {
+        let query = normalize(query);
+        let terms = query.split_whitespace().collect::<Vec<_>>();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut candidates: Option<HashSet<usize>> = None;
+        for term in terms.iter() {
+            let ids = self.candidates(term);
+            candidates = Some(match candidates {
+                Some(current) => current.intersection(&ids).cloned().collect(),
+                None => ids,
+            });
+        }
+
+        let mut hits = candidates
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| self.score(id, &terms))
+            .collect::<Vec<_>>();
+
+        hits.sort_by(|(id1, hit1), (id2, hit2)| hit2.score.cmp(&hit1.score).then(id1.cmp(id2)));
+        hits.truncate(k);
+        hits.into_iter().map(|(_, hit)| hit).collect()
+    }
+
+    /// Butterflies which contain every n-gram of `term`
+    fn candidates(&self, term: &str) -> HashSet<usize> {
+        let chars = term.chars().collect::<Vec<_>>();
+        let size = NGRAM_SIZE.min(chars.len());
+        let mut ids: Option<HashSet<usize>> = None;
+
+        for window in chars.windows(size) {
+            let ngram = window.iter().collect::<String>();
+            let found = match self.ngrams.get(&ngram) {
+                Some(found) => found,
+                None => return HashSet::new(),
+            };
+            ids = Some(match ids {
+                Some(current) => current.intersection(found).cloned().collect(),
+                None => found.to_owned(),
+            });
+        }
+
+        ids.unwrap_or_default()
+    }
+
+    /// Score the butterfly at `id`, or `None` if a term is not found
+    fn score(&self, id: usize, terms: &[&str]) -> Option<(usize, SearchHit<'a>)> {
+        let document = &self.documents[id];
+        let mut score = 0;
+        let mut fields = Vec::new();
+
+        for term in terms.iter() {
+            let mut best: Option<(SearchField, u32)> = None;
+            for (field, text) in document.fields.iter() {
+                let term_score = match match_kind(text, term) {
+                    Some(kind) => kind * field.weight(),
+                    None => continue,
+                };
+                if best.map(|(_, s)| term_score > s).unwrap_or(true) {
+                    best = Some((*field, term_score));
+                }
+            }
+
+            let (field, term_score) = best?;
+            score += term_score;
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+
+        Some((
+            id,
+            SearchHit {
+                butterfly: &self.butterflies[id],
+                score,
+                fields,
+            },
+        ))
+    }
+}
+
+/// Normalize `text` for searching
+///
+/// Runs `csv_data::normalize`, then converts hiragana into katakana and
+/// lowercases latin letters.
+pub(crate) fn normalize(text: &str) -> String {
+    let text = csv_data::normalize(text);
+    kana::hira2kata(&text).to_lowercase()
+}
+
+/// Score how `term` matches `text`: 3 for the whole text, 2 for the start of
+/// a word and 1 for anywhere else
+fn match_kind(text: &str, term: &str) -> Option<u32> {
+    if text == term {
+        return Some(3);
+    }
+
+    let mut found = false;
+    for (start, _) in text.match_indices(term) {
+        let at_word_start = text[..start]
+            .chars()
+            .last()
+            .map(|c| !c.is_alphanumeric())
+            .unwrap_or(true);
+        if at_word_start {
+            return Some(2);
+        }
+        found = true;
+    }
+
+    if found {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// N-grams of `text`, including shorter ones so that single characters can
+/// be searched
+fn text_ngrams(text: &str) -> HashSet<String> {
+    let chars = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<_>>();
+    let mut ngrams = HashSet::new();
+
+    for size in 1..=NGRAM_SIZE {
+        for window in chars.windows(size) {
+            ngrams.insert(window.iter().collect::<String>());
+        }
+    }
+    ngrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kana_variants_find_same_butterflies() {
+        let butterflies = vec![
+            Butterfly {
+                distribution: "日本全土".to_string(),
+                ..Butterfly::named("", "ナミアゲハ", "Papilio xuthus")
+            },
+            Butterfly {
+                distribution: "本州以南".to_string(),
+                ..Butterfly::named("", "アオスジアゲハ", "Graphium sarpedon")
+            },
+            Butterfly {
+                distribution: "日本全土".to_string(),
+                ..Butterfly::named("", "モンシロチョウ", "Pieris rapae")
+            },
+        ];
+        let index = SearchIndex::new(&butterflies);
+        let names = |query| {
+            index
+                .search(query, 10)
+                .into_iter()
+                .map(|hit| hit.butterfly.jp_name.to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let swallowtails = vec!["ナミアゲハ".to_string(), "アオスジアゲハ".to_string()];
+        assert_eq!(names("あげは"), swallowtails);
+        assert_eq!(names("ｱｹﾞﾊ"), swallowtails);
+        assert_eq!(names("アゲハ"), swallowtails);
+        assert_eq!(names("ＰＡＰＩＬＩＯ"), vec!["ナミアゲハ".to_string()]);
+        assert_eq!(names("rapae"), vec!["モンシロチョウ".to_string()]);
+        assert!(names("タテハ").is_empty());
+        assert!(names("  ").is_empty());
+    }
+
+    #[test]
+    fn prefix_matches_rank_higher() {
+        let butterflies = vec![
+            Butterfly {
+                distribution: "本州".to_string(),
+                ..Butterfly::named("", "ヒメシジミ", "Plebejus argus")
+            },
+            Butterfly {
+                distribution: "本州".to_string(),
+                ..Butterfly::named("", "シジミチョウ", "Lycaenidae")
+            },
+            Butterfly {
+                distribution: "日本全土".to_string(),
+                ..Butterfly::named("", "ベニシジミ", "Lycaena phlaeas")
+            },
+        ];
+        let index = SearchIndex::new(&butterflies);
+
+        let hits = index.search("しじみ", 10);
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].butterfly.jp_name, "シジミチョウ");
+        assert_eq!(hits[0].fields, vec![SearchField::JpName]);
+
+        let hits = index.search("しじみ 全土", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].butterfly.jp_name, "ベニシジミ");
+        assert_eq!(
+            hits[0].fields,
+            vec![SearchField::JpName, SearchField::Distribution]
+        );
+        assert_eq!(index.search("しじみ", 1).len(), 1);
+    }
+}
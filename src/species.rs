@@ -0,0 +1,185 @@
+//! # Species
+//!
+//! This module exports `Species`, which groups the butterflies of the same
+//! species collected in several regions, or with images of both sexes, into a
+//! single record. Each butterfly is kept as an `Occurrence` along with its
+//! names, image and pdf file, as butterflies of a species may be listed under
+//! different Japanese names such as the underside of the wings.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::butterfly::Butterfly;
+use super::butterfly_id::{name_key, ButterflyId};
+use super::scientific_name::{strip_sex_markers, ScientificName, Sex};
+
+/// Butterfly of a species found in a region
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Occurrence {
+    /// Id of the butterfly
+    pub id: ButterflyId,
+    /// Japanese name as listed on the website
+    pub jp_name: String,
+    /// English name as listed on the website
+    pub eng_name: String,
+    /// Category
+    pub category: String,
+    /// Region
+    pub region: String,
+    /// Directory to store the assets
+    pub dir_name: String,
+    /// Sex given by a marker such as `♀` in the names
+    pub sex: Option<Sex>,
+    /// Url of an image
+    pub img_src: String,
+    /// Path to image
+    pub img_path: Option<String>,
+    /// Url to pdf
+    pub pdf_src: String,
+    /// Path to pdf file
+    pub pdf_path: String,
+    /// Url of the region page
+    pub url: String,
+}
+
+impl Occurrence {
+    /// Create an new instance of `Occurrence`
+    fn new(butterfly: &Butterfly) -> Occurrence {
+        Occurrence {
+            id: butterfly.id.to_owned(),
+            jp_name: butterfly.jp_name.to_owned(),
+            eng_name: butterfly.eng_name.to_owned(),
+            category: butterfly.category.to_owned(),
+            region: butterfly.region.to_owned(),
+            dir_name: butterfly.dir_name.to_owned(),
+            sex: butterfly.sex,
+            img_src: butterfly.img_src.to_owned(),
+            img_path: butterfly.img_path.to_owned(),
+            pdf_src: butterfly.pdf_src.to_owned(),
+            pdf_path: butterfly.pdf_path.to_owned(),
+            url: butterfly.url.to_owned(),
+        }
+    }
+}
+
+/// Species and every occurrence of it
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Species {
+    /// Normalized name identifying the species, as used in `ButterflyId`
+    pub key: String,
+    /// Japanese names without sex markers, in the order they were found
+    pub jp_names: Vec<String>,
+    /// English name of the first occurrence, without sex markers
+    pub eng_name: String,
+    /// Scientific name, if `eng_name` is in binomial form
+    pub scientific_name: Option<ScientificName>,
+    /// Category of the first occurrence
+    pub category: String,
+    /// Occurrences in the order the butterflies were given
+    pub occurrences: Vec<Occurrence>,
+}
+
+impl Species {
+    /// Group `butterflies` by species, sorted by the Japanese name
+    pub fn group(butterflies: &[Butterfly]) -> Vec<Species> {
+        let mut species: BTreeMap<String, Species> = BTreeMap::new();
+
+        for butterfly in butterflies.iter() {
+            let key = name_key(
+                butterfly.scientific_name.as_ref(),
+                &butterfly.eng_name,
+                &butterfly.jp_name,
+            );
+            let entry = species.entry(key.to_owned()).or_insert_with(|| Species {
+                key,
+                jp_names: Vec::new(),
+                eng_name: strip_sex_markers(&butterfly.eng_name),
+                scientific_name: butterfly.scientific_name.to_owned(),
+                category: butterfly.category.to_owned(),
+                occurrences: Vec::new(),
+            });
+            let jp_name = strip_sex_markers(&butterfly.jp_name);
+            if !entry.jp_names.contains(&jp_name) {
+                entry.jp_names.push(jp_name);
+            }
+            entry.occurrences.push(Occurrence::new(butterfly));
+        }
+
+        let mut species = species.into_values().collect::<Vec<_>>();
+        species.sort_by(|s1, s2| s1.jp_name().cmp(s2.jp_name()).then(s1.key.cmp(&s2.key)));
+        species
+    }
+
+    /// Japanese name the species was first found under
+    pub fn jp_name(&self) -> &str {
+        self.jp_names
+            .first()
+            .map(|name| name.as_str())
+            .unwrap_or("")
+    }
+
+    /// Regions where the species was found, without duplicates
+    pub fn regions(&self) -> Vec<&str> {
+        let mut regions: Vec<&str> = Vec::new();
+        for occurrence in self.occurrences.iter() {
+            if !regions.contains(&occurrence.region.as_str()) {
+                regions.push(&occurrence.region);
+            }
+        }
+        regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn occurrences_are_grouped_by_species() {
+        let butterflies = vec![
+            Butterfly {
+                img_src: "a.jpg".to_string(),
+                ..Butterfly::named("旧北区", "ツマムラサキマダラ♀", "Euploea_mulcibe")
+            },
+            Butterfly {
+                img_src: "b.jpg".to_string(),
+                ..Butterfly::named("旧北区", "ツマムラサキマダラ♂", "Euploea_mulcibe")
+            },
+            Butterfly {
+                img_src: "c.jpg".to_string(),
+                ..Butterfly::named("東洋区", "ツマムラサキマダラ♀", "Euploea_mulcibe")
+            },
+            Butterfly {
+                img_src: "d.jpg".to_string(),
+                ..Butterfly::named("旧北区", "キアゲハ", "Papilio_machaon")
+            },
+            Butterfly {
+                img_src: "e.jpg".to_string(),
+                ..Butterfly::named("旧北区", "キアゲハ裏", "Papilio_machaon")
+            },
+        ];
+
+        let species = Species::group(&butterflies);
+        assert_eq!(species.len(), 2);
+        assert_eq!(species[0].jp_names, vec!["キアゲハ", "キアゲハ裏"]);
+        assert_eq!(species[0].occurrences[1].jp_name, "キアゲハ裏");
+
+        let euploea = &species[1];
+        assert_eq!(euploea.jp_name(), "ツマムラサキマダラ");
+        assert_eq!(euploea.jp_names, vec!["ツマムラサキマダラ"]);
+        assert_eq!(euploea.key, "euploea mulcibe");
+        assert_eq!(euploea.regions(), vec!["旧北区", "東洋区"]);
+        assert_eq!(
+            euploea
+                .occurrences
+                .iter()
+                .map(|o| (o.img_src.as_str(), o.sex))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a.jpg", Some(Sex::Female)),
+                ("b.jpg", Some(Sex::Male)),
+                ("c.jpg", Some(Sex::Female)),
+            ]
+        );
+    }
+}
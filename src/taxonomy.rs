@@ -0,0 +1,262 @@
+//! # Taxonomy
+//!
+//! This module exports `Taxonomy`, a family → genus → species tree built from
+//! collected butterflies. Families come from `Butterfly::category`, such as
+//! `アゲハチョウ科`, and are given their Latin name when known. Genera and
+//! species come from `Butterfly::scientific_name`.
+//!
+//! ```rust
+//! let taxonomy = butterfly_data.region_taxonomy("old_north");
+//! for family in taxonomy.families.iter() {
+//!     println!("{} {:?}", family.jp_name, family.latin_name);
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+
+use super::butterfly::Butterfly;
+use super::scientific_name::{strip_sex_markers, ScientificName};
+
+// Japanese family names used on the website and their Latin names
+const FAMILY_NAMES: [(&str, &str); 13] = [
+    ("アゲハチョウ科", "Papilionidae"),
+    ("シロチョウ科", "Pieridae"),
+    ("シジミチョウ科", "Lycaenidae"),
+    ("シジミタテハ科", "Riodinidae"),
+    ("タテハチョウ科", "Nymphalidae"),
+    ("マダラチョウ科", "Danaidae"),
+    ("ジャノメチョウ科", "Satyridae"),
+    ("テングチョウ科", "Libytheidae"),
+    ("ワモンチョウ科", "Amathusiidae"),
+    ("モルフォチョウ科", "Morphidae"),
+    ("ドクチョウ科", "Heliconiidae"),
+    ("トンボマダラ科", "Ithomiidae"),
+    ("セセリチョウ科", "Hesperiidae"),
+];
+
+/// Latin name of a family given its Japanese name, such as `Papilionidae`
+/// for `アゲハチョウ科`
+pub fn latin_family_name(jp_name: &str) -> Option<&'static str> {
+    let jp_name = jp_name.trim();
+    FAMILY_NAMES
+        .iter()
+        .find(|(jp, _)| *jp == jp_name)
+        .map(|(_, latin)| *latin)
+}
+
+/// Species in `Taxonomy`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpeciesNode {
+    /// Scientific name
+    pub scientific_name: ScientificName,
+    /// Japanese names without sex markers
+    pub jp_names: Vec<String>,
+    /// Regions where the species was collected
+    pub regions: Vec<String>,
+}
+
+/// Genus in `Taxonomy`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenusNode {
+    /// Name of the genus such as `Papilio`
+    pub name: String,
+    /// Species of the genus
+    pub species: Vec<SpeciesNode>,
+}
+
+/// Family in `Taxonomy`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FamilyNode {
+    /// Japanese name such as `アゲハチョウ科`
+    pub jp_name: String,
+    /// Latin name such as `Papilionidae`, if known
+    pub latin_name: Option<String>,
+    /// Genera of the family
+    pub genera: Vec<GenusNode>,
+    /// Japanese names of butterflies without a valid scientific name
+    pub unclassified: Vec<String>,
+}
+
+impl FamilyNode {
+    /// Lookup a genus by its name
+    pub fn genus(&self, name: &str) -> Option<&GenusNode> {
+        self.genera.iter().find(|genus| genus.name == name)
+    }
+}
+
+/// Family → genus → species tree of butterflies
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Taxonomy {
+    /// Families sorted by their Japanese name
+    pub families: Vec<FamilyNode>,
+}
+
+#[derive(Default)]
+struct FamilyEntries {
+    genera: BTreeMap<String, BTreeMap<ScientificName, SpeciesEntries>>,
+    unclassified: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct SpeciesEntries {
+    jp_names: BTreeSet<String>,
+    regions: BTreeSet<String>,
+}
+
+impl Taxonomy {
+    /// Build `Taxonomy` from `butterflies`
+    pub fn new(butterflies: &[Butterfly]) -> Taxonomy {
+        let mut families: BTreeMap<String, FamilyEntries> = BTreeMap::new();
+
+        for butterfly in butterflies.iter() {
+            let family = families
+                .entry(butterfly.category.trim().to_string())
+                .or_default();
+            let jp_name = strip_sex_markers(&butterfly.jp_name);
+
+            match &butterfly.scientific_name {
+                Some(scientific_name) => {
+                    let species = family
+                        .genera
+                        .entry(scientific_name.genus.to_owned())
+                        .or_default()
+                        .entry(scientific_name.to_owned())
+                        .or_default();
+                    species.jp_names.insert(jp_name);
+                    species.regions.insert(butterfly.region.to_owned());
+                }
+                None => {
+                    family.unclassified.insert(jp_name);
+                }
+            }
+        }
+
+        let families = families
+            .into_iter()
+            .map(|(jp_name, entries)| FamilyNode {
+                latin_name: latin_family_name(&jp_name).map(|name| name.to_string()),
+                jp_name,
+                genera: entries
+                    .genera
+                    .into_iter()
+                    .map(|(name, species)| GenusNode {
+                        name,
+                        species: species
+                            .into_iter()
+                            .map(|(scientific_name, entries)| SpeciesNode {
+                                scientific_name,
+                                jp_names: entries.jp_names.into_iter().collect(),
+                                regions: entries.regions.into_iter().collect(),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+                unclassified: entries.unclassified.into_iter().collect(),
+            })
+            .collect();
+
+        Taxonomy { families }
+    }
+
+    /// Build `Taxonomy` from butterflies of given region
+    ///
+    /// `region` is either the name of the region such as `旧北区` or its
+    /// directory name such as `old_north`.
+    pub fn from_region(butterflies: &[Butterfly], region: &str) -> Taxonomy {
+        let butterflies = butterflies
+            .iter()
+            .filter(|b| b.region == region || b.dir_name == region)
+            .cloned()
+            .collect::<Vec<_>>();
+        Taxonomy::new(&butterflies)
+    }
+
+    /// Lookup a family by either its Japanese or Latin name
+    pub fn family(&self, name: &str) -> Option<&FamilyNode> {
+        self.families.iter().find(|family| {
+            family.jp_name == name
+                || family
+                    .latin_name
+                    .as_deref()
+                    .map(|latin| latin.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+        })
+    }
+
+    /// Number of species in the tree
+    pub fn species_num(&self) -> usize {
+        self.families
+            .iter()
+            .flat_map(|family| family.genera.iter())
+            .map(|genus| genus.species.len())
+            .sum()
+    }
+
+    /// Store the tree as JSON file
+    pub fn store_json(&self, file_path: &str) -> Result<(), std::io::Error> {
+        let json_file = File::create(file_path)?;
+        serde_json::to_writer_pretty(json_file, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn butterflies_are_grouped_by_family_genus_and_species() {
+        let butterflies = vec![
+            Butterfly {
+                category: "アゲハチョウ科".to_string(),
+                ..Butterfly::named("旧北区", "キアゲハ", "Papilio_machaon")
+            },
+            Butterfly {
+                category: "アゲハチョウ科".to_string(),
+                ..Butterfly::named("新北区", "キアゲハ", "Papilio_machaon")
+            },
+            Butterfly {
+                category: "アゲハチョウ科".to_string(),
+                ..Butterfly::named("旧北区", "ナミアゲハ", "Papilio_xuthus")
+            },
+            Butterfly {
+                category: "マダラチョウ科".to_string(),
+                ..Butterfly::named("旧北区", "ツマムラサキマダラ♀", "Euploea_mulcibe")
+            },
+            Butterfly {
+                category: "マダラチョウ科".to_string(),
+                ..Butterfly::named("旧北区", "ツマムラサキマダラ♂", "Euploea_mulcibe")
+            },
+            Butterfly {
+                category: "不明科".to_string(),
+                ..Butterfly::named("旧北区", "ナゾノチョウ", "unknown")
+            },
+        ];
+        let taxonomy = Taxonomy::new(&butterflies);
+
+        assert_eq!(taxonomy.families.len(), 3);
+        assert_eq!(taxonomy.species_num(), 3);
+
+        let papilionidae = taxonomy.family("papilionidae").unwrap();
+        assert_eq!(papilionidae.jp_name, "アゲハチョウ科");
+        let papilio = papilionidae.genus("Papilio").unwrap();
+        assert_eq!(papilio.species.len(), 2);
+        assert_eq!(papilio.species[0].regions, vec!["新北区", "旧北区"]);
+
+        let danaidae = taxonomy.family("マダラチョウ科").unwrap();
+        assert_eq!(
+            danaidae.genus("Euploea").unwrap().species[0].jp_names,
+            vec!["ツマムラサキマダラ"]
+        );
+
+        let unknown = taxonomy.family("不明科").unwrap();
+        assert_eq!(unknown.latin_name, None);
+        assert_eq!(unknown.unclassified, vec!["ナゾノチョウ"]);
+
+        let region = Taxonomy::from_region(&butterflies, "新北区");
+        assert_eq!(region.families.len(), 1);
+        assert_eq!(region.species_num(), 1);
+    }
+}
@@ -3,30 +3,105 @@
 //! This module exports modules which are used to extract data from
 //! butterfly website
 
-use log::error;
+use log::{error, warn};
 use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
 use super::butterfly::Butterfly;
+use super::butterfly_id::disambiguate_ids;
 use super::errors::ButterflyError;
-
-// Encoding used on the butterfly website
-const WEBSITE_CHARSET: &str = "Shift-JIS";
+use super::http_client::HttpClient;
+use super::name_overrides::NameOverrides;
+use super::page_source::PageSource;
 
 type Id = usize;
 
+// Maximum length of the cell html kept in `ParseIssue`
+const ISSUE_SNIPPET_LENGTH: usize = 200;
+
+/// Options which decide how strictly region pages are parsed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Skip bad cells and record them in `ParseReport` instead of failing
+    pub lenient: bool,
+    /// Number of bad cells tolerated in a region when `lenient` is set
+    pub error_threshold: usize,
+}
+
+impl ParseOptions {
+    /// Create `ParseOptions` which fails on the first bad cell
+    pub fn strict() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    /// Create `ParseOptions` which skips bad cells, failing only when more
+    /// than `error_threshold` of them are found in a region
+    pub fn lenient(error_threshold: usize) -> ParseOptions {
+        ParseOptions {
+            lenient: true,
+            error_threshold,
+        }
+    }
+}
+
+/// Bad cell found while parsing a region page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseIssue {
+    /// Name of the region
+    pub region: String,
+    /// Index of the table in the page
+    pub table_index: usize,
+    /// Index of the row in the table
+    pub row_index: usize,
+    /// Html of the cell, truncated
+    pub cell: String,
+    /// Reason why the cell was skipped
+    pub reason: String,
+}
+
+/// Collection of `ParseIssue` found while parsing
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseReport {
+    /// List of bad cells
+    pub issues: Vec<ParseIssue>,
+}
+
+impl ParseReport {
+    /// Check if no issue has been found
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Number of issues found
+    pub fn len(&self) -> usize {
+        self.issues.len()
+    }
+
+    /// Append issues of other report
+    pub fn merge(&mut self, other: &ParseReport) {
+        self.issues.extend(other.issues.iter().cloned());
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WebpageParseResult {
     /// Directory name to store assets
     pub dir_name: String,
     /// Name of the region
     pub region: String,
+    /// English name of the region
+    pub eng_region: Option<String>,
     /// Url of region page
     pub url: String,
     /// Collections of butterflies
     pub butterflies: HashMap<Id, Butterfly>,
     /// Pdf collection
     pub pdfs: HashSet<(String, String)>,
+    /// Number of times each name override has been applied, keyed by its raw text
+    pub used_name_overrides: HashMap<String, usize>,
+    /// Bad cells skipped while parsing
+    pub report: ParseReport,
 }
 
 impl WebpageParseResult {
@@ -34,36 +109,36 @@ impl WebpageParseResult {
         WebpageParseResult {
             dir_name: parser.dir_name.to_owned(),
             region: parser.region.to_owned(),
+            eng_region: parser.eng_region.to_owned(),
             url: parser.url.to_owned(),
             butterflies: HashMap::new(),
             pdfs: HashSet::new(),
+            used_name_overrides: HashMap::new(),
+            report: ParseReport::default(),
         }
     }
 
-    /// Insert new `Butterfly` to `butterflies`
+    /// Insert new `Butterfly` to `butterflies`, returning its id
     fn insert_butterfly(
         &mut self,
         img_src: &str,
         pdf_src: &str,
         color: &str,
         category: &str,
-    ) -> Option<&mut WebpageParseResult> {
+    ) -> Id {
         let id = self.butterflies.len();
-        match self.butterflies.insert(
-            id,
-            Butterfly::new(
-                &self.region,
-                img_src,
-                pdf_src,
-                color,
-                category,
-                &self.dir_name,
-                &self.url,
-            ),
-        ) {
-            Some(_old_val) => None,
-            None => Some(self),
-        }
+        let mut butterfly = Butterfly::new(
+            &self.region,
+            img_src,
+            pdf_src,
+            color,
+            category,
+            &self.dir_name,
+            &self.url,
+        );
+        butterfly.eng_region = self.eng_region.to_owned();
+        self.butterflies.insert(id, butterfly);
+        id
     }
 
     /// Lookup `Butterfly` with given `id`, and update its name
@@ -77,8 +152,66 @@ impl WebpageParseResult {
         }
     }
 
+    /// Record a bad cell
+    ///
+    /// Returns `err` as is unless `options` is lenient, and fails when the
+    /// number of bad cells exceeds the threshold.
+    fn record_issue(
+        &mut self,
+        options: &ParseOptions,
+        err: ButterflyError,
+        table_index: usize,
+        row_index: usize,
+        td: &ElementRef,
+    ) -> Result<(), ButterflyError> {
+        if !options.lenient {
+            return Err(err);
+        }
+
+        let cell = td.html().chars().take(ISSUE_SNIPPET_LENGTH).collect();
+        warn!("Skipping cell in {}: {}", self.region, err);
+        self.report.issues.push(ParseIssue {
+            region: self.region.to_owned(),
+            table_index,
+            row_index,
+            cell,
+            reason: err.to_string(),
+        });
+
+        if self.report.len() > options.error_threshold {
+            return Err(ButterflyError::TooManyParseErrors(
+                self.region.to_owned(),
+                self.report.len(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Remove butterflies whose cells were skipped, along with pdfs which are
+    /// no longer referenced
+    fn remove_rejected(&mut self, rejected: &HashSet<Id>) {
+        if rejected.is_empty() {
+            return;
+        }
+
+        self.butterflies.retain(|id, _| !rejected.contains(id));
+        let referenced = self
+            .butterflies
+            .values()
+            .map(|b| b.pdf_src.to_owned())
+            .collect::<HashSet<String>>();
+        self.pdfs
+            .retain(|(pdf_src, _)| referenced.contains(pdf_src));
+    }
+
     ///Parse given html and extract information from it
-    fn parse_page(&mut self, html: &str) -> Result<(), ButterflyError> {
+    fn parse_page(
+        &mut self,
+        html: &str,
+        overrides: &NameOverrides,
+        options: &ParseOptions,
+    ) -> Result<(), ButterflyError> {
         let fragment = Html::parse_document(html);
 
         // Selectors we would use for parsing
@@ -92,56 +225,94 @@ impl WebpageParseResult {
         let mut name_id = 0;
         let mut color_category_map: HashMap<String, String> = HashMap::new();
         let mut table_color = "#ffffff";
+        // Butterflies whose image or name cell was skipped
+        let mut rejected: HashSet<Id> = HashSet::new();
 
-        for table in fragment.select(&table_selector) {
+        for (table_index, table) in fragment.select(&table_selector).enumerate() {
             if let Some(color) = table.value().attr("bgcolor") {
                 table_color = color;
             };
             for tbody in table.select(&tbody_selector) {
-                for tr in tbody.select(&tr_selector) {
+                for (row_index, tr) in tbody.select(&tr_selector).enumerate() {
                     if !is_category_section(&tr) {
                         for td in tr.select(&td_selector) {
                             // If a cell has img element, then extract img source
                             // as well as background color
                             if let Some(img) = td.select(&img_selector).next() {
-                                if let Some(src) = img.value().attr("src") {
-                                    //Extract the url to pdf here
-                                    let (color, category) = extract_color_category(
-                                        src,
-                                        table_color,
-                                        &td,
-                                        &color_category_map,
-                                    );
-                                    let href = td
-                                        .select(&a_selector)
-                                        .next()
-                                        .unwrap()
-                                        .value()
-                                        .attr("href")
-                                        .unwrap();
-                                    self.pdfs
-                                        .insert((href.to_owned(), self.dir_name.to_owned()));
-                                    self.insert_butterfly(src, href, &color, &category);
-                                } else {
-                                    //throw error
-                                    return Err(ButterflyError::ImageSourceNotFound);
+                                let src = img.value().attr("src");
+                                //Extract the url to pdf here
+                                let href = td
+                                    .select(&a_selector)
+                                    .next()
+                                    .and_then(|a| a.value().attr("href"));
+                                let (color, category) = extract_color_category(
+                                    src.unwrap_or(""),
+                                    table_color,
+                                    &td,
+                                    &color_category_map,
+                                );
+                                // Butterfly is inserted even if the cell is bad
+                                // so that the names below stay aligned
+                                let id = self.insert_butterfly(
+                                    src.unwrap_or(""),
+                                    href.unwrap_or(""),
+                                    &color,
+                                    &category,
+                                );
+                                let problem = match (src, href) {
+                                    (None, _) => Some(ButterflyError::ImageSourceNotFound),
+                                    (_, None) => Some(ButterflyError::LinkNotFound),
+                                    (Some(_), Some(href)) => {
+                                        self.pdfs
+                                            .insert((href.to_owned(), self.dir_name.to_owned()));
+                                        None
+                                    }
+                                };
+                                if let Some(err) = problem {
+                                    self.record_issue(options, err, table_index, row_index, &td)?;
+                                    rejected.insert(id);
                                 }
                             // If a cell does not have a img source, then extract
                             // names from it
                             } else {
                                 // Ignore empty cell
-                                if !is_empty_text(&(td.clone().text().collect::<String>())) {
-                                    if let Some((jp_name, eng_name)) = get_jp_en_name(td) {
-                                        if self.add_names(&jp_name, &eng_name, name_id) {
-                                            name_id += 1;
-                                        } else {
-                                            return Err(
-                                                ButterflyError::InvalidIndexButterflyNotFound,
-                                            );
-                                        };
-                                    } else {
-                                        return Err(ButterflyError::TextNotFound);
+                                let text = td.text().collect::<String>();
+                                if !is_empty_text(&text) {
+                                    let names = match overrides.find(&text) {
+                                        Some(name_override) => {
+                                            *self
+                                                .used_name_overrides
+                                                .entry(name_override.raw.to_owned())
+                                                .or_insert(0) += 1;
+                                            Some((
+                                                name_override.jp_name.to_owned(),
+                                                name_override.eng_name.to_owned(),
+                                            ))
+                                        }
+                                        None => get_jp_en_name(&text),
+                                    };
+                                    let id = name_id;
+                                    name_id += 1;
+                                    let problem = match names {
+                                        Some((jp_name, eng_name)) => {
+                                            if self.add_names(&jp_name, &eng_name, id) {
+                                                None
+                                            } else {
+                                                Some(ButterflyError::InvalidIndexButterflyNotFound)
+                                            }
+                                        }
+                                        None => Some(ButterflyError::TextNotFound),
                                     };
+                                    if let Some(err) = problem {
+                                        self.record_issue(
+                                            options,
+                                            err,
+                                            table_index,
+                                            row_index,
+                                            &td,
+                                        )?;
+                                        rejected.insert(id);
+                                    }
                                 }
                             };
                         }
@@ -156,8 +327,18 @@ impl WebpageParseResult {
             }
         }
 
+        self.remove_rejected(&rejected);
+        self.disambiguate_ids();
+
         Ok(())
     }
+
+    /// Tell apart butterflies sharing an id, in the order of the cells
+    fn disambiguate_ids(&mut self) {
+        let mut butterflies = self.butterflies.iter_mut().collect::<Vec<_>>();
+        butterflies.sort_unstable_by_key(|(id, _)| **id);
+        disambiguate_ids(butterflies.into_iter().map(|(_, butterfly)| butterfly));
+    }
 }
 
 /// This struct is used to initialize web parsing extraction process
@@ -167,8 +348,12 @@ pub struct WebpageParser {
     pub dir_name: String,
     /// Name of the region
     pub region: String,
+    /// English name of the region
+    pub eng_region: Option<String>,
     /// Url of region page
     pub url: String,
+    /// Where the html of the region page is read from
+    pub source: PageSource,
 }
 
 impl WebpageParser {
@@ -185,27 +370,51 @@ impl WebpageParser {
         WebpageParser {
             dir_name: dir_name.to_string(),
             region: region.to_string(),
+            eng_region: None,
             url: url.to_string(),
+            source: PageSource::Http(url.to_string()),
         }
     }
 
-    /// Extract informations of butterflies from `url`
-    pub(crate) fn fetch_data(&mut self) -> Result<WebpageParseResult, ButterflyError> {
-        let body = request_html(&self.url)
-            .map_err(|_e| ButterflyError::FailedToFetchHTML(self.url.clone()))?;
+    /// Set English name of the region
+    pub fn with_eng_region(mut self, eng_region: &str) -> WebpageParser {
+        self.eng_region = Some(eng_region.to_string());
+        self
+    }
+
+    /// Read the region page from `source` instead of fetching it from `url`
+    ///
+    /// `url` is still used to fill in `Butterfly::url`.
+    ///
+    /// ```rust
+    /// WebpageParser::new(
+    ///     "old_north",
+    ///     "旧北区",
+    ///     "http://biokite.com/worldbutterfly/butterfly-PArc.htm#PAall"
+    /// )
+    /// .with_source(PageSource::file("./snapshots/old_north.htm"))
+    /// ```
+    pub fn with_source(mut self, source: PageSource) -> WebpageParser {
+        self.source = source;
+        self
+    }
+
+    /// Extract informations of butterflies from `source`, correcting names
+    /// of the cells listed in `overrides`
+    pub(crate) fn fetch_data(
+        &mut self,
+        http: &HttpClient,
+        overrides: &NameOverrides,
+        options: &ParseOptions,
+    ) -> Result<WebpageParseResult, ButterflyError> {
+        let body = self.source.read(http)?;
         let mut result = WebpageParseResult::new(self);
-        result.parse_page(&body)?;
+        result.parse_page(&body, overrides, options)?;
 
         Ok(result)
     }
 }
 
-///Fetch content of given `url`
-fn request_html(url: &str) -> Result<String, reqwest::Error> {
-    let mut req = reqwest::get(url)?;
-    req.text_with_charset(WEBSITE_CHARSET)
-}
-
 ///Check if given tr set are category cells
 fn is_category_section(element: &ElementRef) -> bool {
     let mut is_category = false;
@@ -277,32 +486,17 @@ fn extract_color_category(
     (color.to_string(), category.to_string())
 }
 
-///Extract both Japanese and English name from given `ElementRef`
-fn get_jp_en_name(td: ElementRef) -> Option<(String, String)> {
-    let td = td.text().collect::<String>();
-
+///Extract both Japanese and English name from the text of a cell
+///
+/// Cells which do not follow the usual layout are handled by `NameOverrides`
+fn get_jp_en_name(text: &str) -> Option<(String, String)> {
     let mut names = vec![];
-    for line in td.lines() {
+    for line in text.lines() {
         names.push(line);
     }
 
-    let jp_name;
-    let eng_name;
-
-    //Handling exceptions
-    if names == vec!["ヒメアカタテハCynthia_cardui"] {
-        jp_name = Some("ヒメアカタテハ");
-        eng_name = Some("Cynthia_cardui");
-    } else if names == vec!["ツマムラサキマダラ♀Euploea_mulcibe"] {
-        jp_name = Some("ツマムラサキマダラ♀");
-        eng_name = Some("Euploea_mulcibe");
-    } else if names.get(0).cloned() == Some("ミイロタイマイ") {
-        jp_name = Some("ミイロタイマイ");
-        eng_name = Some("Graphium_weiskei");
-    } else {
-        jp_name = names.get(0).cloned();
-        eng_name = names.get(1).cloned();
-    }
+    let jp_name = names.get(0).cloned();
+    let eng_name = names.get(1).cloned();
 
     match (jp_name, eng_name) {
         (Some(jp), Some(eng)) => {
@@ -312,3 +506,314 @@ fn get_jp_en_name(td: ElementRef) -> Option<(String, String)> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::butterfly_id::ButterflyId;
+    use crate::name_overrides::{MatchOn, NameOverride};
+    use serde::{Deserialize, Serialize};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // Region pages trimmed from the website, saved in Shift-JIS
+    const FIXTURE_DIRECTORY: &str = "tests/fixtures/region_pages";
+    // Name overrides shipped with the crate
+    const NAME_OVERRIDES_PATH: &str = "name_overrides.json";
+
+    /// Expected outcome of parsing a fixture page
+    ///
+    /// Run the tests with `UPDATE_FIXTURES=1` to regenerate the json files
+    /// after an intended change in the parser.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Golden {
+        butterflies: Vec<Butterfly>,
+        pdfs: Vec<(String, String)>,
+        issues: Vec<ParseIssue>,
+    }
+
+    impl Golden {
+        fn from_result(result: &WebpageParseResult) -> Golden {
+            let mut ids = result.butterflies.keys().collect::<Vec<&Id>>();
+            ids.sort();
+            let butterflies = ids
+                .into_iter()
+                .map(|id| result.butterflies[id].to_owned())
+                .collect();
+            let mut pdfs = result.pdfs.iter().cloned().collect::<Vec<_>>();
+            pdfs.sort();
+
+            Golden {
+                butterflies,
+                pdfs,
+                issues: result.report.issues.to_owned(),
+            }
+        }
+    }
+
+    fn fixture_path(name: &str, extension: &str) -> PathBuf {
+        Path::new(FIXTURE_DIRECTORY).join(format!("{}.{}", name, extension))
+    }
+
+    fn parse_fixture(name: &str) -> Result<WebpageParseResult, ButterflyError> {
+        parse_fixture_with(name, &ParseOptions::strict())
+    }
+
+    fn parse_fixture_with(
+        name: &str,
+        options: &ParseOptions,
+    ) -> Result<WebpageParseResult, ButterflyError> {
+        WebpageParser::new(
+            "india_australia",
+            "インド・オーストラリア区",
+            "http://biokite.com/worldbutterfly/butterfly-IOrs.htm#IOmap",
+        )
+        .with_eng_region("Indo-Australian")
+        .with_source(PageSource::file(fixture_path(name, "htm")))
+        .fetch_data(
+            &HttpClient::default(),
+            &NameOverrides::from_path(NAME_OVERRIDES_PATH).unwrap(),
+            options,
+        )
+    }
+
+    fn assert_golden(name: &str, options: &ParseOptions) {
+        let result = parse_fixture_with(name, options).unwrap();
+        let actual = Golden::from_result(&result);
+        let golden_path = fixture_path(name, "json");
+
+        if std::env::var("UPDATE_FIXTURES").is_ok() {
+            let json = serde_json::to_string_pretty(&actual).unwrap();
+            fs::write(&golden_path, json + "\n").unwrap();
+        }
+
+        let expected: Golden =
+            serde_json::from_str(&fs::read_to_string(&golden_path).unwrap()).unwrap();
+        assert_eq!(actual, expected, "parse result of {} changed", name);
+    }
+
+    fn first_tr(html: &str) -> Html {
+        Html::parse_fragment(&format!("<table>{}</table>", html))
+    }
+
+    #[test]
+    fn golden_category_rows() {
+        assert_golden("categories", &ParseOptions::strict());
+    }
+
+    #[test]
+    fn golden_papilionidae_table() {
+        assert_golden("papilionidae", &ParseOptions::strict());
+    }
+
+    #[test]
+    fn name_cell_without_english_name_is_rejected() {
+        match parse_fixture("missing_text") {
+            Err(ButterflyError::TextNotFound) => (),
+            other => panic!("expected TextNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn names_without_image_are_rejected() {
+        match parse_fixture("orphan_names") {
+            Err(ButterflyError::InvalidIndexButterflyNotFound) => (),
+            other => panic!("expected InvalidIndexButterflyNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn golden_malformed_cells_are_skipped_when_lenient() {
+        assert_golden("malformed", &ParseOptions::lenient(3));
+    }
+
+    #[test]
+    fn cell_without_link_is_rejected() {
+        match parse_fixture("missing_link") {
+            Err(ButterflyError::LinkNotFound) => (),
+            other => panic!("expected LinkNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lenient_parsing_fails_over_threshold() {
+        match parse_fixture_with("malformed", &ParseOptions::lenient(2)) {
+            Err(ButterflyError::TooManyParseErrors(_, 3)) => (),
+            other => panic!("expected TooManyParseErrors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn category_section_detection() {
+        let tr_selector = Selector::parse("tr").unwrap();
+        let cases = vec![
+            (r##"<tr><td colspan="2">タテハチョウ科</td></tr>"##, true),
+            (r##"<tr><td>タテハチョウ科</td></tr>"##, false),
+            (
+                r##"<tr><td colspan="2" bgcolor="#ffff66" width="337">x</td></tr>"##,
+                false,
+            ),
+            (
+                r##"<tr><td colspan="2" bgcolor="#ffff66">x</td></tr>"##,
+                true,
+            ),
+        ];
+
+        for (html, expected) in cases {
+            let fragment = first_tr(html);
+            let tr = fragment.select(&tr_selector).next().unwrap();
+            assert_eq!(is_category_section(&tr), expected, "{}", html);
+        }
+    }
+
+    #[test]
+    fn color_category_pairs_fall_back_to_table_color() {
+        let tr_selector = Selector::parse("tr").unwrap();
+        let fragment = first_tr(
+            r##"<tr><td colspan="2" bgcolor="#ccffff">マダラチョウ科</td><td>シジミチョウ科</td><td>　</td></tr>"##,
+        );
+        let tr = fragment.select(&tr_selector).next().unwrap();
+
+        assert_eq!(
+            extract_color_category_vec("#ffffff", &tr),
+            vec![
+                ("#ccffff".to_string(), "マダラチョウ科".to_string()),
+                ("#ffffff".to_string(), "シジミチョウ科".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn jp_en_name_from_text() {
+        assert_eq!(
+            get_jp_en_name("アサギマダラ\n  Parantica_sita "),
+            Some(("アサギマダラ".to_string(), "Parantica_sita".to_string()))
+        );
+        assert_eq!(get_jp_en_name("アサギマダラ"), None);
+    }
+
+    #[test]
+    fn name_overrides_are_reported() {
+        let overrides = NameOverrides::from_path(NAME_OVERRIDES_PATH).unwrap();
+        let result = parse_fixture("categories").unwrap();
+        let report = overrides.report(&result.used_name_overrides);
+
+        assert_eq!(
+            report.used,
+            vec![
+                ("ヒメアカタテハCynthia_cardui".to_string(), 1),
+                ("ツマムラサキマダラ♀Euploea_mulcibe".to_string(), 1),
+            ]
+        );
+        assert_eq!(report.unused, vec!["ミイロタイマイ".to_string()]);
+    }
+
+    #[test]
+    fn butterflies_of_same_species_in_a_region_have_distinct_ids() {
+        let html = r#"<html><body><table border="1">
+            <tr><td colspan="2">アゲハチョウ科</td></tr>
+            <tr>
+                <td><a href="butterfly_pdf/p/p-pa_1.pdf"><img src="butterfly_img/p/machaon.jpg"></a></td>
+                <td><a href="butterfly_pdf/p/p-pa_1.pdf"><img src="butterfly_img/p/machaon_ura.jpg"></a></td>
+                <td><a href="butterfly_pdf/p/p-pa_1.pdf"><img src="butterfly_img/p/machaon_2.jpg"></a></td>
+            </tr>
+            <tr>
+                <td>キアゲハ
+                Papilio_machaon</td>
+                <td>キアゲハ裏
+                Papilio_machaon</td>
+                <td>キアゲハ
+                Papilio_machaon</td>
+            </tr>
+            </table></body></html>"#;
+        let result = WebpageParser::new("old_north", "旧北区", "http://biokite.com/")
+            .with_source(PageSource::Text(html.to_string()))
+            .fetch_data(
+                &HttpClient::default(),
+                &NameOverrides::default(),
+                &ParseOptions::strict(),
+            )
+            .unwrap();
+
+        let ids = result
+            .butterflies
+            .values()
+            .map(|b| b.id.to_owned())
+            .collect::<HashSet<_>>();
+        assert_eq!(result.butterflies.len(), 3);
+        assert_eq!(ids.len(), 3);
+        // Only the later of the colliding butterflies is told apart by image
+        for id in 0..2 {
+            assert_eq!(
+                result.butterflies[&id].id,
+                ButterflyId::of(&result.butterflies[&id])
+            );
+        }
+        assert_ne!(
+            result.butterflies[&2].id,
+            ButterflyId::of(&result.butterflies[&2])
+        );
+    }
+
+    #[test]
+    fn bundled_overrides_are_used_by_default() {
+        assert_eq!(
+            NameOverrides::default(),
+            NameOverrides::from_path(NAME_OVERRIDES_PATH).unwrap()
+        );
+        assert!(NameOverrides::default()
+            .find("ヒメアカタテハCynthia_cardui")
+            .is_some());
+    }
+
+    #[test]
+    fn layered_overrides_are_tried_first() {
+        let mut overrides = NameOverrides::default();
+        overrides.layer(NameOverrides::new(vec![NameOverride {
+            raw: "ヒメアカタテハCynthia_cardui".to_string(),
+            match_on: MatchOn::Text,
+            jp_name: "ヒメアカタテハ".to_string(),
+            eng_name: "Vanessa_cardui".to_string(),
+        }]));
+
+        let found = overrides.find("ヒメアカタテハCynthia_cardui").unwrap();
+        assert_eq!(found.eng_name, "Vanessa_cardui");
+        assert_eq!(
+            overrides.report(&HashMap::new()).unused.len(),
+            NameOverrides::default()
+                .report(&HashMap::new())
+                .unused
+                .len()
+        );
+    }
+
+    #[test]
+    fn name_override_match_modes() {
+        let overrides = NameOverrides::new(vec![
+            NameOverride {
+                raw: "ヒメアカタテハCynthia_cardui".to_string(),
+                match_on: MatchOn::Text,
+                jp_name: "ヒメアカタテハ".to_string(),
+                eng_name: "Cynthia_cardui".to_string(),
+            },
+            NameOverride {
+                raw: "ミイロタイマイ".to_string(),
+                match_on: MatchOn::FirstLine,
+                jp_name: "ミイロタイマイ".to_string(),
+                eng_name: "Graphium_weiskei".to_string(),
+            },
+        ]);
+
+        let found = |text| overrides.find(text).map(|o| o.eng_name.as_str());
+        assert_eq!(
+            found("ヒメアカタテハCynthia_cardui\n"),
+            Some("Cynthia_cardui")
+        );
+        assert_eq!(found("ヒメアカタテハ\nCynthia_cardui"), None);
+        assert_eq!(
+            found("ミイロタイマイ\nGraphium weiskei"),
+            Some("Graphium_weiskei")
+        );
+        assert_eq!(found("アサギマダラ\nParantica_sita"), None);
+    }
+}